@@ -0,0 +1,171 @@
+use crate::diff::hash_line;
+use crate::error::Result;
+use crate::file_reader::{FileReader, ReloadResult};
+
+/// Lines from the top of the view remembered to find it again.
+pub const ANCHOR_LINES: usize = 8;
+
+/// How many lines either side of its old place the text at the top of the
+/// view is looked for.
+const SEARCH_RADIUS: usize = 10_000;
+
+/// The text at the top of the view, remembered so the view can stay with it
+/// when an edit inserts or deletes lines above it.
+pub struct Anchor {
+    /// File line that was at the top
+    line: usize,
+    /// Hashes of that line and those after it
+    hashes: Vec<u64>,
+}
+
+impl Anchor {
+    /// Remembers `texts`, consecutive lines of the file starting at `line`.
+    pub fn new<'a>(line: usize, texts: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            line,
+            hashes: texts.into_iter().take(ANCHOR_LINES).map(hash_line).collect(),
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Whether the change `result` reports could have moved the remembered
+    /// text, so that it is worth looking for. Appending leaves it where it
+    /// was, as does an edit starting after it. `generation_start` is the
+    /// line the current file starts at.
+    pub fn may_have_moved(&self, result: &ReloadResult, generation_start: usize) -> bool {
+        match result {
+            ReloadResult::NoChange | ReloadResult::Appended | ReloadResult::Deleted => false,
+            ReloadResult::Edited { first_line } => {
+                generation_start + first_line < self.line + self.hashes.len()
+            }
+            _ => true,
+        }
+    }
+
+    /// The file line where the remembered text now starts: the place near
+    /// the old one whose lines agree with it most, or the nearest of those
+    /// tied. `None` if no place agrees on at least half the lines, as when
+    /// the text itself was rewritten.
+    pub fn find(&self, file_reader: &mut FileReader) -> Result<Option<usize>> {
+        let start = self.line.saturating_sub(SEARCH_RADIUS);
+        let count = self.line - start + SEARCH_RADIUS + self.hashes.len();
+        let hashes: Vec<u64> = file_reader
+            .get_lines(start, count)?
+            .iter()
+            .map(|line| hash_line(line))
+            .collect();
+
+        let needed = self.hashes.len().div_ceil(2).max(1);
+        let mut best: Option<(usize, usize)> = None;
+        for offset in 0..hashes.len() {
+            let line = start + offset;
+            let score = self
+                .hashes
+                .iter()
+                .zip(&hashes[offset..])
+                .filter(|(old, new)| old == new)
+                .count();
+            let better = match best {
+                None => score >= needed,
+                Some((best_score, best_line)) => {
+                    score > best_score
+                        || (score == best_score
+                            && line.abs_diff(self.line) < best_line.abs_diff(self.line))
+                }
+            };
+            if better {
+                best = Some((score, line));
+            }
+        }
+
+        Ok(best.map(|(_, line)| line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_reader::FollowMode;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn numbered(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("line {:03}", i)).collect()
+    }
+
+    /// Remembers the text at line 50 of a hundred numbered lines, then
+    /// rewrites the file as `edit` leaves them and looks for the text.
+    fn find_after(edit: impl FnOnce(&mut Vec<String>)) -> Option<usize> {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("app.log");
+        let write = |lines: &[String]| {
+            fs::write(&path, lines.iter().map(|line| format!("{}\n", line)).collect::<String>())
+                .unwrap()
+        };
+
+        let mut lines = numbered(100);
+        write(&lines);
+        let mut reader = FileReader::new(&path, None, FollowMode::Name, false).unwrap();
+        reader.index_all().unwrap();
+        let anchor = Anchor::new(50, lines[50..].iter().map(String::as_str));
+
+        edit(&mut lines);
+        write(&lines);
+        reader.reload().unwrap();
+        reader.index_all().unwrap();
+        anchor.find(&mut reader).unwrap()
+    }
+
+    #[test]
+    fn text_pushed_down_is_found_below() {
+        let found = find_after(|lines| {
+            lines.splice(10..10, ["new".to_string(), "new".to_string(), "new".to_string()]);
+        });
+        assert_eq!(found, Some(53));
+    }
+
+    #[test]
+    fn text_pulled_up_is_found_above() {
+        assert_eq!(find_after(|lines| drop(lines.drain(10..15))), Some(45));
+    }
+
+    #[test]
+    fn the_nearest_copy_of_the_text_wins() {
+        let found = find_after(|lines| {
+            let copy = lines[50..58].to_vec();
+            lines.splice(60..60, copy);
+            lines.drain(0..2);
+        });
+        assert_eq!(found, Some(48));
+    }
+
+    #[test]
+    fn a_deleted_top_line_leaves_the_lines_after_it_in_place() {
+        // The line above takes its place, so the rest stay on the same rows
+        assert_eq!(find_after(|lines| drop(lines.remove(50))), Some(49));
+    }
+
+    #[test]
+    fn rewritten_text_is_not_found() {
+        let found = find_after(|lines| {
+            for line in &mut lines[40..60] {
+                *line = "other".to_string();
+            }
+        });
+        assert_eq!(found, None);
+    }
+
+    #[test]
+    fn appends_and_later_edits_leave_the_text_in_place() {
+        let texts = numbered(8);
+        let anchor = Anchor::new(50, texts.iter().map(String::as_str));
+        assert!(!anchor.may_have_moved(&ReloadResult::Appended, 0));
+        assert!(!anchor.may_have_moved(&ReloadResult::Edited { first_line: 58 }, 0));
+        assert!(anchor.may_have_moved(&ReloadResult::Edited { first_line: 57 }, 0));
+        assert!(!anchor.may_have_moved(&ReloadResult::Edited { first_line: 8 }, 50));
+        assert!(anchor.may_have_moved(&ReloadResult::Modified, 0));
+    }
+}
@@ -0,0 +1,302 @@
+use crossterm::style::Color;
+use std::borrow::Cow;
+use std::ops::Range;
+
+/// Text attributes set by SGR (Select Graphic Rendition) escape sequences.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Style {
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+    pub crossed_out: bool,
+}
+
+/// A line with its escape sequences removed, and the styles its SGR
+/// sequences gave to parts of what is left.
+pub struct StyledLine {
+    pub text: String,
+    /// Byte ranges of `text` in order, leaving out unstyled stretches
+    pub spans: Vec<(Range<usize>, Style)>,
+}
+
+impl StyledLine {
+    /// The style of the text at byte `offset`.
+    pub fn style_at(&self, offset: usize) -> Style {
+        let i = self.spans.partition_point(|(range, _)| range.end <= offset);
+        self.spans
+            .get(i)
+            .filter(|(range, _)| range.start <= offset)
+            .map(|&(_, style)| style)
+            .unwrap_or_default()
+    }
+}
+
+/// Parses the escape sequences in `line`. SGR sequences become styles, and
+/// everything else, such as cursor movement, is dropped. Each line starts
+/// unstyled, so a style left on at the end of a line doesn't leak into the
+/// next.
+pub fn parse(line: &str) -> StyledLine {
+    let mut text = String::with_capacity(line.len());
+    let mut spans = Vec::new();
+    let mut style = Style::default();
+    let mut span_start = 0;
+    let mut rest = line;
+
+    while let Some(escape) = rest.find('\x1b') {
+        text.push_str(&rest[..escape]);
+        rest = &rest[escape..];
+
+        let (length, parameters) = sequence(rest.as_bytes());
+        if let Some(parameters) = parameters {
+            let next = style.apply(&rest[2..2 + parameters]);
+            if next != style {
+                if style != Style::default() && text.len() > span_start {
+                    spans.push((span_start..text.len(), style));
+                }
+                span_start = text.len();
+                style = next;
+            }
+        }
+        rest = &rest[length..];
+    }
+    text.push_str(rest);
+    if style != Style::default() && text.len() > span_start {
+        spans.push((span_start..text.len(), style));
+    }
+
+    StyledLine { text, spans }
+}
+
+/// The text of `line` as it is shown: without its escape sequences when
+/// they are being interpreted, and untouched otherwise.
+pub fn visible_text(line: &str, raw_control_chars: bool) -> Cow<'_, str> {
+    if raw_control_chars && line.contains('\x1b') {
+        Cow::Owned(parse(line).text)
+    } else {
+        Cow::Borrowed(line)
+    }
+}
+
+/// Measures the escape sequence at the start of `bytes`, returning its
+/// length and, for an SGR sequence, the length of its parameters after the
+/// leading `ESC [`. A sequence cut off by the end of the line runs to the
+/// end of the line.
+fn sequence(bytes: &[u8]) -> (usize, Option<usize>) {
+    match bytes.get(1) {
+        // CSI: parameter and intermediate bytes, then a final byte
+        Some(b'[') => {
+            for (i, &byte) in bytes.iter().enumerate().skip(2) {
+                match byte {
+                    0x20..=0x3F => {}
+                    b'm' => {
+                        let parameters = &bytes[2..i];
+                        let is_sgr = parameters
+                            .iter()
+                            .all(|&b| b.is_ascii_digit() || b == b';' || b == b':');
+                        return (i + 1, is_sgr.then_some(i - 2));
+                    }
+                    0x40..=0x7E => return (i + 1, None),
+                    // Not part of a valid sequence, so leave it as text
+                    _ => return (i, None),
+                }
+            }
+            (bytes.len(), None)
+        }
+        // OSC, DCS and the other string sequences, ended by BEL or `ESC \`
+        Some(b']' | b'P' | b'X' | b'^' | b'_') => {
+            for i in 2..bytes.len() {
+                if bytes[i] == 0x07 {
+                    return (i + 1, None);
+                }
+                if bytes[i] == 0x1b && bytes.get(i + 1) == Some(&b'\\') {
+                    return (i + 2, None);
+                }
+            }
+            (bytes.len(), None)
+        }
+        // Other escapes: intermediate bytes, then a final byte
+        Some(_) => {
+            for (i, &byte) in bytes.iter().enumerate().skip(1) {
+                match byte {
+                    0x20..=0x2F => {}
+                    0x30..=0x7E => return (i + 1, None),
+                    _ => return (i, None),
+                }
+            }
+            (bytes.len(), None)
+        }
+        None => (1, None),
+    }
+}
+
+impl Style {
+    /// The style after applying the SGR `parameters`, such as `1;31`.
+    fn apply(mut self, parameters: &str) -> Self {
+        let codes: Vec<u16> = parameters
+            .split([';', ':'])
+            .map(|code| code.parse().unwrap_or(0))
+            .collect();
+
+        let mut i = 0;
+        while i < codes.len() {
+            match codes[i] {
+                0 => self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.reverse = true,
+                9 => self.crossed_out = true,
+                22 => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.reverse = false,
+                29 => self.crossed_out = false,
+                code @ 30..=37 => self.foreground = Some(Color::AnsiValue((code - 30) as u8)),
+                code @ 90..=97 => self.foreground = Some(Color::AnsiValue((code - 90 + 8) as u8)),
+                39 => self.foreground = None,
+                code @ 40..=47 => self.background = Some(Color::AnsiValue((code - 40) as u8)),
+                code @ 100..=107 => {
+                    self.background = Some(Color::AnsiValue((code - 100 + 8) as u8))
+                }
+                49 => self.background = None,
+                code @ (38 | 48) => {
+                    let (color, used) = extended_color(&codes[i + 1..]);
+                    if code == 38 {
+                        self.foreground = color;
+                    } else {
+                        self.background = color;
+                    }
+                    i += used;
+                }
+                // Blinking, fonts and the like are left out
+                _ => {}
+            }
+            i += 1;
+        }
+
+        self
+    }
+}
+
+/// Reads the color after a 38 or 48 code, either `5;N` from the 256-color
+/// palette or `2;R;G;B`, returning it with the number of codes it took.
+fn extended_color(codes: &[u16]) -> (Option<Color>, usize) {
+    let byte = |i: usize| codes.get(i).map(|&c| c.min(255) as u8);
+    match codes.first() {
+        Some(5) => (byte(1).map(Color::AnsiValue), 2),
+        Some(2) => match (byte(1), byte(2), byte(3)) {
+            (Some(r), Some(g), Some(b)) => (Some(Color::Rgb { r, g, b }), 4),
+            _ => (None, codes.len()),
+        },
+        _ => (None, codes.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(change: impl FnOnce(&mut Style)) -> Style {
+        let mut style = Style::default();
+        change(&mut style);
+        style
+    }
+
+    #[test]
+    fn text_without_escapes_is_left_alone() {
+        let line = parse("plain text");
+        assert_eq!(line.text, "plain text");
+        assert!(line.spans.is_empty());
+    }
+
+    #[test]
+    fn sgr_sequences_become_spans() {
+        let line = parse("\x1b[1;31mred\x1b[0m plain");
+        assert_eq!(line.text, "red plain");
+        let bold_red = style(|s| {
+            s.bold = true;
+            s.foreground = Some(Color::AnsiValue(1));
+        });
+        assert_eq!(line.spans, [(0..3, bold_red)]);
+        assert_eq!(line.style_at(2), bold_red);
+        assert_eq!(line.style_at(3), Style::default());
+    }
+
+    #[test]
+    fn attributes_are_turned_off_one_at_a_time() {
+        let line = parse("\x1b[1;4mx\x1b[22my\x1b[24mz");
+        assert_eq!(line.text, "xyz");
+        assert_eq!(
+            line.spans,
+            [
+                (
+                    0..1,
+                    style(|s| {
+                        s.bold = true;
+                        s.underline = true;
+                    })
+                ),
+                (1..2, style(|s| s.underline = true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn palette_and_rgb_colors_are_read() {
+        let line = parse("\x1b[38;5;208ma\x1b[48;2;1;2;3mb\x1b[94mc");
+        let orange = Some(Color::AnsiValue(208));
+        assert_eq!(
+            line.spans,
+            [
+                (0..1, style(|s| s.foreground = orange)),
+                (
+                    1..2,
+                    style(|s| {
+                        s.foreground = orange;
+                        s.background = Some(Color::Rgb { r: 1, g: 2, b: 3 });
+                    })
+                ),
+                (
+                    2..3,
+                    style(|s| {
+                        s.foreground = Some(Color::AnsiValue(12));
+                        s.background = Some(Color::Rgb { r: 1, g: 2, b: 3 });
+                    })
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn other_sequences_are_dropped() {
+        let line = parse("a\x1b[2Kb\x1b]0;title\x07c\x1b(Bd\x1b]8;;url\x1b\\e");
+        assert_eq!(line.text, "abcde");
+        assert!(line.spans.is_empty());
+    }
+
+    #[test]
+    fn a_sequence_cut_off_at_the_end_is_dropped() {
+        assert_eq!(parse("a\x1b[31").text, "a");
+        assert_eq!(parse("a\x1b").text, "a");
+    }
+
+    #[test]
+    fn a_style_left_on_runs_to_the_end_of_the_line() {
+        let line = parse("\x1b[1mbold");
+        assert_eq!(line.spans, [(0..4, style(|s| s.bold = true))]);
+    }
+
+    #[test]
+    fn visible_text_strips_escapes_only_when_interpreting_them() {
+        assert_eq!(visible_text("\x1b[31mred", true), "red");
+        assert_eq!(visible_text("\x1b[31mred", false), "\x1b[31mred");
+    }
+}
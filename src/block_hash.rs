@@ -0,0 +1,275 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Bytes covered by each hash.
+pub const BLOCK_SIZE: u64 = 64 * 1024;
+
+/// Hashes of consecutive fixed-size blocks of a file, taken as the file is
+/// read, so a later version can be checked for starting with the same bytes
+/// without keeping the old bytes around.
+///
+/// The hash is a fast multiplicative one rather than a cryptographic one:
+/// it only has to notice edits, not resist forgery, and it is kept up with
+/// the line index scanning the whole file.
+pub struct BlockHashes {
+    /// Offset of the first byte hashed
+    start: u64,
+    /// Hashes of the complete blocks seen so far
+    blocks: Vec<u64>,
+    /// Hash of the bytes of the block still being filled
+    partial: PartialHash,
+}
+
+impl BlockHashes {
+    pub fn new(start: u64) -> Self {
+        Self {
+            start,
+            blocks: Vec::new(),
+            partial: PartialHash::new(),
+        }
+    }
+
+    /// Offset just past the last byte hashed.
+    pub fn end(&self) -> u64 {
+        self.start + self.blocks.len() as u64 * BLOCK_SIZE + self.partial.len
+    }
+
+    /// Hashes `bytes`, which continue where the last call left off.
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let room = (BLOCK_SIZE - self.partial.len) as usize;
+            let (now, rest) = bytes.split_at(room.min(bytes.len()));
+            self.partial.update(now);
+            if self.partial.len == BLOCK_SIZE {
+                let full = std::mem::replace(&mut self.partial, PartialHash::new());
+                self.blocks.push(full.finish());
+            }
+            bytes = rest;
+        }
+    }
+
+    /// How many bytes from the start of what was hashed `reader` still has
+    /// unchanged, to the nearest block below, or exactly if it matches in
+    /// full. `len` is the length of the data `reader` now holds.
+    pub fn common_prefix<R: Read + Seek>(&self, reader: &mut R, len: u64) -> io::Result<u64> {
+        reader.seek(SeekFrom::Start(self.start))?;
+        let mut buffer = vec![0; BLOCK_SIZE as usize];
+        let mut offset = self.start;
+
+        for &expected in &self.blocks {
+            if offset + BLOCK_SIZE > len {
+                return Ok(offset);
+            }
+            reader.read_exact(&mut buffer)?;
+            if hash(&buffer) != expected {
+                return Ok(offset);
+            }
+            offset += BLOCK_SIZE;
+        }
+
+        let tail = self.partial.len;
+        if tail > 0 && offset + tail <= len {
+            let buffer = &mut buffer[..tail as usize];
+            reader.read_exact(buffer)?;
+            if hash(buffer) == self.partial.clone().finish() {
+                offset += tail;
+            }
+        }
+        Ok(offset)
+    }
+
+    /// Whether `reader` still has the last complete block hashed and the
+    /// bytes hashed after it unchanged, reading at most two blocks however
+    /// much was hashed. `len` is the length of the data `reader` now holds.
+    pub fn end_matches<R: Read + Seek>(&self, reader: &mut R, len: u64) -> io::Result<bool> {
+        if self.end() > len {
+            return Ok(false);
+        }
+        let mut buffer = vec![0; BLOCK_SIZE as usize];
+        let last_start = self.end() - self.partial.len;
+        match self.blocks.last() {
+            Some(&expected) => {
+                reader.seek(SeekFrom::Start(last_start - BLOCK_SIZE))?;
+                reader.read_exact(&mut buffer)?;
+                if hash(&buffer) != expected {
+                    return Ok(false);
+                }
+            }
+            None => {
+                reader.seek(SeekFrom::Start(last_start))?;
+            }
+        }
+
+        let buffer = &mut buffer[..self.partial.len as usize];
+        reader.read_exact(buffer)?;
+        Ok(buffer.is_empty() || hash(buffer) == self.partial.clone().finish())
+    }
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    let mut partial = PartialHash::new();
+    partial.update(bytes);
+    partial.finish()
+}
+
+const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+/// A hash over bytes fed in pieces of any size, mixing a word at a time.
+#[derive(Clone)]
+struct PartialHash {
+    state: u64,
+    /// Bytes waiting to make up a whole word, in the low bytes
+    pending: u64,
+    pending_len: u32,
+    /// Bytes fed so far
+    len: u64,
+}
+
+impl PartialHash {
+    fn new() -> Self {
+        Self {
+            state: 0,
+            pending: 0,
+            pending_len: 0,
+            len: 0,
+        }
+    }
+
+    fn update(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+
+        while self.pending_len > 0 && !bytes.is_empty() {
+            self.push_byte(bytes[0]);
+            bytes = &bytes[1..];
+        }
+
+        let mut words = bytes.chunks_exact(8);
+        for word in &mut words {
+            self.mix(u64::from_le_bytes(word.try_into().expect("chunk is 8 bytes")));
+        }
+        for &byte in words.remainder() {
+            self.push_byte(byte);
+        }
+    }
+
+    fn push_byte(&mut self, byte: u8) {
+        self.pending |= u64::from(byte) << (8 * self.pending_len);
+        self.pending_len += 1;
+        if self.pending_len == 8 {
+            let word = std::mem::take(&mut self.pending);
+            self.pending_len = 0;
+            self.mix(word);
+        }
+    }
+
+    fn mix(&mut self, word: u64) {
+        self.state = (self.state.rotate_left(5) ^ word).wrapping_mul(SEED);
+    }
+
+    fn finish(mut self) -> u64 {
+        if self.pending_len > 0 {
+            let word = self.pending;
+            self.mix(word);
+        }
+        let len = self.len;
+        self.mix(len);
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const LEN: usize = 2 * BLOCK_SIZE as usize + 1000;
+
+    fn data() -> Vec<u8> {
+        (0..LEN).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    fn hashes_of(data: &[u8], start: u64) -> BlockHashes {
+        let mut hashes = BlockHashes::new(start);
+        // Fed in uneven pieces, as the line index reads them
+        for piece in data[start as usize..].chunks(5000) {
+            hashes.update(piece);
+        }
+        hashes
+    }
+
+    fn common_prefix(hashes: &BlockHashes, data: &[u8]) -> u64 {
+        hashes
+            .common_prefix(&mut Cursor::new(data), data.len() as u64)
+            .unwrap()
+    }
+
+    #[test]
+    fn unchanged_data_matches_in_full() {
+        let data = data();
+        let hashes = hashes_of(&data, 0);
+        assert_eq!(hashes.end(), LEN as u64);
+        assert_eq!(common_prefix(&hashes, &data), LEN as u64);
+    }
+
+    #[test]
+    fn appended_data_matches_up_to_the_old_end() {
+        let data = data();
+        let hashes = hashes_of(&data, 0);
+        let mut longer = data.clone();
+        longer.extend_from_slice(b"more");
+        assert_eq!(common_prefix(&hashes, &longer), LEN as u64);
+    }
+
+    #[test]
+    fn a_change_stops_the_match_at_the_start_of_its_block() {
+        let data = data();
+        let hashes = hashes_of(&data, 0);
+        for (at, prefix) in [
+            (0, 0),
+            (BLOCK_SIZE as usize + 10, BLOCK_SIZE),
+            (LEN - 1, 2 * BLOCK_SIZE),
+        ] {
+            let mut changed = data.clone();
+            changed[at] ^= 1;
+            assert_eq!(common_prefix(&hashes, &changed), prefix, "change at {}", at);
+        }
+    }
+
+    #[test]
+    fn shorter_data_matches_only_whole_blocks_it_still_has() {
+        let data = data();
+        let hashes = hashes_of(&data, 0);
+        let cut = &data[..BLOCK_SIZE as usize + 10];
+        assert_eq!(common_prefix(&hashes, cut), BLOCK_SIZE);
+    }
+
+    #[test]
+    fn the_end_matches_unless_the_last_blocks_changed() {
+        let data = data();
+        let hashes = hashes_of(&data, 0);
+        let end_matches = |data: &[u8]| {
+            hashes
+                .end_matches(&mut Cursor::new(data), data.len() as u64)
+                .unwrap()
+        };
+        let mut longer = data.clone();
+        longer.extend_from_slice(b"more");
+        assert!(end_matches(&longer));
+        assert!(!end_matches(&data[..LEN - 1]));
+
+        // Only the last complete block and the partial one are read
+        for (at, matches) in [(10, true), (BLOCK_SIZE as usize + 10, false), (LEN - 1, false)] {
+            let mut changed = longer.clone();
+            changed[at] ^= 1;
+            assert_eq!(end_matches(&changed), matches, "change at {}", at);
+        }
+    }
+
+    #[test]
+    fn hashing_starts_at_the_given_offset() {
+        let data = data();
+        let hashes = hashes_of(&data, 3);
+        let mut changed = data.clone();
+        changed[0] ^= 1;
+        assert_eq!(common_prefix(&hashes, &changed), LEN as u64);
+    }
+}
@@ -0,0 +1,305 @@
+use crate::diff;
+use crate::error::Result;
+use crate::file_reader::{FileReader, ReloadResult};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// Largest file whose lines are all remembered to tell what a reload
+/// changed. Beyond it only appended lines are marked.
+const MAX_TRACKED_BYTES: u64 = 16 * 1024 * 1024;
+
+/// How the lines of a changed region differ from before.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangeKind {
+    Added,
+    Changed,
+    /// Lines were removed just above this one
+    Deleted,
+}
+
+/// How far a mark has faded, in thirds of the fade time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shade {
+    Bright,
+    Normal,
+    Dim,
+}
+
+/// The lines that changed on the last reload.
+///
+/// Hashes of every line are kept while the file is small enough, and each
+/// reload that does more than append is diffed against them. The marks
+/// fade after a while, but the regions can be jumped between until the
+/// next change.
+pub struct Changes {
+    fade: Duration,
+    /// Hashes of every line as last read, unless the file is too large
+    hashes: Option<Vec<u64>>,
+    /// File lines that changed, in order
+    regions: Vec<(Range<usize>, ChangeKind)>,
+    marked_at: Instant,
+}
+
+impl Changes {
+    /// Starts remembering the lines of `file_reader`, marking them for
+    /// `fade` after each change.
+    pub fn new(file_reader: &mut FileReader, fade: Duration) -> Result<Self> {
+        Ok(Self {
+            fade,
+            hashes: Self::hash_file(file_reader)?,
+            regions: Vec::new(),
+            marked_at: Instant::now(),
+        })
+    }
+
+    /// Works out which lines `result` changed. `known_end` is the number
+    /// of lines before the reload, if the file had been indexed to its end.
+    pub fn update(
+        &mut self,
+        file_reader: &mut FileReader,
+        result: &ReloadResult,
+        known_end: Option<usize>,
+    ) -> Result<()> {
+        match result {
+            ReloadResult::NoChange | ReloadResult::Deleted => return Ok(()),
+            ReloadResult::Appended => self.mark_appended(file_reader, known_end)?,
+            _ => match self.hashes.take() {
+                Some(old) => {
+                    let new = Self::hash_file(file_reader)?;
+                    self.regions = match &new {
+                        Some(new) => Self::regions_between(&old, new),
+                        None => Vec::new(),
+                    };
+                    self.hashes = new;
+                }
+                None => {
+                    self.regions.clear();
+                    self.hashes = Self::hash_file(file_reader)?;
+                }
+            },
+        }
+        self.marked_at = Instant::now();
+        Ok(())
+    }
+
+    /// Marks the lines after the old end as added, and the old last line as
+    /// changed if it was completed.
+    fn mark_appended(&mut self, file_reader: &mut FileReader, known_end: Option<usize>) -> Result<()> {
+        self.regions.clear();
+        let Some(old_end) = known_end else {
+            self.hashes = None;
+            return Ok(());
+        };
+        file_reader.index_all()?;
+        let end = file_reader.total_lines();
+
+        if file_reader.byte_len() > MAX_TRACKED_BYTES {
+            self.hashes = None;
+        } else if let Some(hashes) = self.hashes.as_mut() {
+            let last = old_end.saturating_sub(1);
+            let old_last = hashes.get(last).copied();
+            hashes.truncate(last);
+            hashes.extend(diff::hash_lines(file_reader, last..end)?);
+            if old_last.is_some() && hashes.get(last).copied() != old_last {
+                self.regions.push((last..old_end, ChangeKind::Changed));
+            }
+        }
+
+        if old_end < end {
+            self.regions.push((old_end..end, ChangeKind::Added));
+        }
+        Ok(())
+    }
+
+    /// The changed regions of the new version, from the hunks of the diff.
+    fn regions_between(old: &[u64], new: &[u64]) -> Vec<(Range<usize>, ChangeKind)> {
+        diff::diff(old, new)
+            .into_iter()
+            .filter_map(|hunk| {
+                if hunk.new.is_empty() {
+                    // Mark the line that took the place of deleted ones
+                    let line = hunk.new.start.min(new.len().checked_sub(1)?);
+                    Some((line..line + 1, ChangeKind::Deleted))
+                } else if hunk.old.is_empty() {
+                    Some((hunk.new, ChangeKind::Added))
+                } else {
+                    Some((hunk.new, ChangeKind::Changed))
+                }
+            })
+            .collect()
+    }
+
+    /// Hashes every line of the file, if it is small enough to remember.
+    fn hash_file(file_reader: &mut FileReader) -> Result<Option<Vec<u64>>> {
+        if file_reader.byte_len() > MAX_TRACKED_BYTES {
+            return Ok(None);
+        }
+        file_reader.index_all()?;
+        let end = file_reader.total_lines();
+        diff::hash_lines(file_reader, 0..end).map(Some)
+    }
+
+    /// How `line` changed on the last reload, if it did.
+    pub fn kind_at(&self, line: usize) -> Option<ChangeKind> {
+        let i = self.regions.partition_point(|(range, _)| range.end <= line);
+        self.regions
+            .get(i)
+            .filter(|(range, _)| range.contains(&line))
+            .map(|&(_, kind)| kind)
+    }
+
+    /// How far the marks have faded, or `None` once they are no longer
+    /// shown.
+    pub fn shade(&self) -> Option<Shade> {
+        let elapsed = self.marked_at.elapsed();
+        if self.regions.is_empty() || elapsed >= self.fade {
+            return None;
+        }
+        Some(if elapsed < self.fade / 3 {
+            Shade::Bright
+        } else if elapsed < self.fade * 2 / 3 {
+            Shade::Normal
+        } else {
+            Shade::Dim
+        })
+    }
+
+    pub fn has_regions(&self) -> bool {
+        !self.regions.is_empty()
+    }
+
+    /// The first line of the first changed region starting after `line`.
+    pub fn next_after(&self, line: usize) -> Option<usize> {
+        self.regions
+            .iter()
+            .map(|(range, _)| range.start)
+            .find(|&start| start > line)
+    }
+
+    /// The first line of the last changed region starting before `line`.
+    pub fn previous_before(&self, line: usize) -> Option<usize> {
+        self.regions
+            .iter()
+            .rev()
+            .map(|(range, _)| range.start)
+            .find(|&start| start < line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_reader::FollowMode;
+    use std::fs;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    /// A file of numbered lines, with its changes tracked.
+    struct Tracked {
+        _dir: TempDir,
+        path: PathBuf,
+        reader: FileReader,
+        changes: Changes,
+    }
+
+    impl Tracked {
+        fn new(content: &str) -> Self {
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("app.log");
+            fs::write(&path, content).unwrap();
+            let mut reader = FileReader::new(&path, None, FollowMode::Name, false).unwrap();
+            let changes = Changes::new(&mut reader, Duration::from_secs(60)).unwrap();
+            Self {
+                _dir: dir,
+                path,
+                reader,
+                changes,
+            }
+        }
+
+        /// Writes `content` over the file and marks what changed, having
+        /// indexed all of it before.
+        fn write(&mut self, content: &str) {
+            self.reader.index_all().unwrap();
+            let known_end = Some(self.reader.total_lines());
+            fs::write(&self.path, content).unwrap();
+            let result = self.reader.reload().unwrap();
+            self.changes.update(&mut self.reader, &result, known_end).unwrap();
+        }
+
+        fn kinds(&self, lines: Range<usize>) -> Vec<Option<ChangeKind>> {
+            lines.map(|line| self.changes.kind_at(line)).collect()
+        }
+    }
+
+    fn numbered(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("line {:03}\n", i)).collect()
+    }
+
+    #[test]
+    fn inserted_lines_are_marked_added() {
+        let mut lines = numbered(30);
+        let mut tracked = Tracked::new(&lines.concat());
+        lines.splice(10..10, ["new\n".to_string(), "new\n".to_string()]);
+        tracked.write(&lines.concat());
+
+        let added = Some(ChangeKind::Added);
+        assert_eq!(tracked.kinds(9..13), [None, added, added, None]);
+        assert_eq!(tracked.changes.shade(), Some(Shade::Bright));
+    }
+
+    #[test]
+    fn removed_lines_mark_the_line_after_them() {
+        let mut lines = numbered(30);
+        let mut tracked = Tracked::new(&lines.concat());
+        lines.drain(10..13);
+        tracked.write(&lines.concat());
+
+        assert_eq!(tracked.kinds(9..12), [None, Some(ChangeKind::Deleted), None]);
+    }
+
+    #[test]
+    fn replaced_lines_are_marked_changed() {
+        let mut lines = numbered(30);
+        let mut tracked = Tracked::new(&lines.concat());
+        lines[10] = "LINE 010\n".to_string();
+        lines[20] = "LINE 020\n".to_string();
+        tracked.write(&lines.concat());
+
+        assert_eq!(tracked.kinds(10..11), [Some(ChangeKind::Changed)]);
+        assert_eq!(tracked.kinds(20..21), [Some(ChangeKind::Changed)]);
+        assert_eq!(tracked.changes.next_after(0), Some(10));
+        assert_eq!(tracked.changes.next_after(10), Some(20));
+        assert_eq!(tracked.changes.previous_before(20), Some(10));
+        assert_eq!(tracked.changes.previous_before(10), None);
+    }
+
+    #[test]
+    fn appending_marks_the_new_lines_and_a_completed_last_line() {
+        let text = numbered(30).concat();
+        let mut tracked = Tracked::new(&format!("{}partial", text));
+        tracked.write(&format!("{}partial line\nmore\n", text));
+
+        let kinds = tracked.kinds(29..32);
+        assert_eq!(kinds, [None, Some(ChangeKind::Changed), Some(ChangeKind::Added)]);
+    }
+
+    #[test]
+    fn edits_to_a_file_too_large_to_remember_are_not_marked() {
+        let line = format!("{}\n", "x".repeat(99));
+        let count = MAX_TRACKED_BYTES as usize / line.len() + 1;
+        let mut lines = vec![line; count];
+        let mut tracked = Tracked::new(&lines.concat());
+        assert!(tracked.changes.hashes.is_none());
+
+        lines[10] = format!("{}\n", "y".repeat(99));
+        tracked.write(&lines.concat());
+        assert!(!tracked.changes.has_regions());
+        assert_eq!(tracked.changes.shade(), None);
+
+        // Appended lines can still be told apart without the old ones
+        lines.push("more\n".to_string());
+        tracked.write(&lines.concat());
+        assert_eq!(tracked.kinds(count..count + 1), [Some(ChangeKind::Added)]);
+    }
+}
@@ -0,0 +1,530 @@
+use crate::encoding::Encoding;
+use crate::error::{MorelError, Result};
+use crate::file_reader::FollowMode;
+use crate::status::{self, StatusTemplate};
+use crate::watcher::WatcherBackend;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+Usage: morel [OPTIONS] [FILE]
+       command | morel [OPTIONS] [-]
+       morel [OPTIONS] --diff OLD NEW
+
+A live file pager that automatically updates when the file changes.
+Reads standard input when FILE is omitted or is `-`.
+
+Options:
+  +G, --end               Start at the end of the file
+  +F, -f, --follow        Start in follow mode
+      --wait              Wait for FILE to be created if it doesn't exist yet
+  +N                      Start at line N
+  +/PATTERN               Start at the first line matching PATTERN
+  -N, --line-numbers      Show line numbers
+  -w, --wrap              Wrap long lines onto several screen rows [default]
+  -S, --chop-long-lines   Cut long lines off at the screen edge
+  -R, --raw-control-chars Show ANSI colors and styles instead of escape codes
+  -x, --tabs N            Set tab stops every N columns [default: 8]
+  -E, --encoding NAME     Read the file as utf-8, utf-16le, utf-16be, latin1 or
+                          windows-1252 instead of detecting it [default: auto]
+      --debounce MS       Wait MS milliseconds for file events to settle [default: 100]
+      --watcher BACKEND   Watch with `native` file events or by `poll`ing [default: native]
+      --follow-by MODE    When the file is rotated, reopen its `name` or keep reading
+                          the old file by `descriptor` [default: name]
+      --generation-markers
+                          Keep a replaced file's lines above the new file's, behind
+                          a marker line
+      --change-fade SECS  Mark lines changed by a reload for SECS seconds, or not at
+                          all if 0 [default: 10]
+      --history N         Keep N past versions of a file that changes other than by
+                          growing [default: 10]
+      --diff OLD NEW      Compare two files, updating as either of them changes
+      --diff-layout LAYOUT
+                          Show diffs `split` side by side or `inline` [default: split]
+      --config PATH       Read settings from PATH instead of the default config file
+  -h, --help              Print help
+  -V, --version           Print version
+
+Press 'h' while viewing to see keyboard shortcuts.";
+
+/// Where the view starts when the file is opened.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StartPosition {
+    Top,
+    End,
+    Line(usize),
+    Pattern(String),
+}
+
+/// Settings that shape how the pager behaves. Defaults are overridden by
+/// the config file and then by command-line flags.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub start: StartPosition,
+    pub follow: bool,
+    /// Whether a missing file is waited for rather than an error
+    pub wait: bool,
+    pub line_numbers: bool,
+    pub wrap: bool,
+    pub tab_width: usize,
+    pub raw_control_chars: bool,
+    /// The file's encoding, or `None` to detect it
+    pub encoding: Option<Encoding>,
+    pub debounce: Duration,
+    pub watcher_backend: WatcherBackend,
+    pub follow_mode: FollowMode,
+    /// Whether a file replaced at the path stays in view above the new one
+    pub generation_markers: bool,
+    /// How long lines changed by a reload stay marked
+    pub change_fade: Duration,
+    /// How many past versions of the file are kept
+    pub history: usize,
+    /// Whether diffs show the two sides next to each other rather than
+    /// inline
+    pub diff_split: bool,
+    /// What the status line shows against its left and right edges
+    pub status_left: StatusTemplate,
+    pub status_right: StatusTemplate,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            start: StartPosition::Top,
+            follow: false,
+            wait: false,
+            line_numbers: false,
+            wrap: true,
+            tab_width: 8,
+            raw_control_chars: false,
+            encoding: None,
+            debounce: Duration::from_millis(100),
+            watcher_backend: WatcherBackend::Native,
+            follow_mode: FollowMode::Name,
+            generation_markers: false,
+            change_fade: Duration::from_secs(10),
+            history: 10,
+            diff_split: true,
+            status_left: StatusTemplate::parse(status::DEFAULT_LEFT)
+                .expect("default status template is valid"),
+            status_right: StatusTemplate::parse(status::DEFAULT_RIGHT)
+                .expect("default status template is valid"),
+        }
+    }
+}
+
+/// What the command line asked for.
+pub enum CliAction {
+    Run(Cli),
+    Help,
+    Version,
+}
+
+/// A parsed command line. Flags are kept separate from `Options` so they
+/// can be applied on top of the config file once it has been read.
+#[derive(Default)]
+pub struct Cli {
+    /// The file to view, or `-` for standard input
+    pub file: Option<String>,
+    pub config_path: Option<PathBuf>,
+    /// The two files to compare instead of viewing one
+    pub diff: Option<(PathBuf, PathBuf)>,
+    start: Option<StartPosition>,
+    follow: Option<bool>,
+    wait: Option<bool>,
+    line_numbers: Option<bool>,
+    wrap: Option<bool>,
+    tab_width: Option<usize>,
+    raw_control_chars: Option<bool>,
+    encoding: Option<Option<Encoding>>,
+    debounce: Option<Duration>,
+    watcher_backend: Option<WatcherBackend>,
+    follow_mode: Option<FollowMode>,
+    generation_markers: Option<bool>,
+    change_fade: Option<Duration>,
+    history: Option<usize>,
+    diff_split: Option<bool>,
+}
+
+impl Cli {
+    /// Parses the arguments after the program name.
+    pub fn parse<I>(args: I) -> Result<CliAction>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut cli = Cli::default();
+        let mut args = args.into_iter();
+        let mut only_files = false;
+
+        while let Some(arg) = args.next() {
+            if only_files {
+                cli.set_file(arg)?;
+                continue;
+            }
+
+            // Split `--name=value` so it is handled like `--name value`
+            let (flag, mut inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => {
+                    (flag.to_string(), Some(value.to_string()))
+                }
+                _ => (arg.clone(), None),
+            };
+            let mut value = |name: &str| {
+                inline_value
+                    .take()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| MorelError::Usage(format!("{} requires a value", name)))
+            };
+
+            match flag.as_str() {
+                "-h" | "--help" => return Ok(CliAction::Help),
+                "-V" | "--version" => return Ok(CliAction::Version),
+                "+G" | "--end" => cli.start = Some(StartPosition::End),
+                "+F" | "-f" | "--follow" => cli.follow = Some(true),
+                "--wait" => cli.wait = Some(true),
+                "-N" | "--line-numbers" => cli.line_numbers = Some(true),
+                "-w" | "--wrap" => cli.wrap = Some(true),
+                "-S" | "--chop-long-lines" => cli.wrap = Some(false),
+                "-R" | "--raw-control-chars" => cli.raw_control_chars = Some(true),
+                "-x" | "--tabs" => {
+                    cli.tab_width = Some(parse_tab_width(&value(&flag)?)?);
+                }
+                "-E" | "--encoding" => {
+                    cli.encoding = Some(parse_encoding(&value(&flag)?)?);
+                }
+                "--debounce" => {
+                    cli.debounce = Some(parse_debounce(&value(&flag)?)?);
+                }
+                "--watcher" => {
+                    cli.watcher_backend = Some(parse_watcher_backend(&value(&flag)?)?);
+                }
+                "--follow-by" => {
+                    cli.follow_mode = Some(parse_follow_mode(&value(&flag)?)?);
+                }
+                "--generation-markers" => cli.generation_markers = Some(true),
+                "--change-fade" => {
+                    cli.change_fade = Some(parse_change_fade(&value(&flag)?)?);
+                }
+                "--history" => {
+                    cli.history = Some(parse_history(&value(&flag)?)?);
+                }
+                "--diff" => {
+                    let old = PathBuf::from(value(&flag)?);
+                    let new = PathBuf::from(value(&flag)?);
+                    cli.diff = Some((old, new));
+                }
+                "--diff-layout" => {
+                    cli.diff_split = Some(parse_diff_layout(&value(&flag)?)?);
+                }
+                "--config" => cli.config_path = Some(PathBuf::from(value(&flag)?)),
+                "--" => only_files = true,
+                "-" => cli.set_file(arg)?,
+                _ if arg.starts_with("+/") => {
+                    cli.start = Some(StartPosition::Pattern(arg[2..].to_string()));
+                }
+                _ if arg.starts_with('+') => {
+                    let line = arg[1..].parse::<usize>().map_err(|_| {
+                        MorelError::Usage(format!("unknown option '{}'", arg))
+                    })?;
+                    cli.start = Some(StartPosition::Line(line));
+                }
+                _ if arg.starts_with('-') => {
+                    return Err(MorelError::Usage(format!("unknown option '{}'", arg)));
+                }
+                _ => cli.set_file(arg)?,
+            }
+
+            if inline_value.is_some() {
+                return Err(MorelError::Usage(format!("{} does not take a value", flag)));
+            }
+        }
+
+        if cli.diff.is_some() && cli.file.is_some() {
+            return Err(MorelError::Usage(
+                "--diff compares two files and can't be given another to view".to_string(),
+            ));
+        }
+
+        Ok(CliAction::Run(cli))
+    }
+
+    fn set_file(&mut self, file: String) -> Result<()> {
+        if self.file.is_some() {
+            return Err(MorelError::Usage("only one file can be viewed at a time".to_string()));
+        }
+        self.file = Some(file);
+        Ok(())
+    }
+
+    /// Overrides `options` with every flag given on the command line.
+    pub fn apply_to(&self, options: &mut Options) {
+        if let Some(start) = &self.start {
+            options.start = start.clone();
+        }
+        if let Some(follow) = self.follow {
+            options.follow = follow;
+        }
+        if let Some(wait) = self.wait {
+            options.wait = wait;
+        }
+        if let Some(line_numbers) = self.line_numbers {
+            options.line_numbers = line_numbers;
+        }
+        if let Some(wrap) = self.wrap {
+            options.wrap = wrap;
+        }
+        if let Some(tab_width) = self.tab_width {
+            options.tab_width = tab_width;
+        }
+        if let Some(raw_control_chars) = self.raw_control_chars {
+            options.raw_control_chars = raw_control_chars;
+        }
+        if let Some(encoding) = self.encoding {
+            options.encoding = encoding;
+        }
+        if let Some(debounce) = self.debounce {
+            options.debounce = debounce;
+        }
+        if let Some(backend) = self.watcher_backend {
+            options.watcher_backend = backend;
+        }
+        if let Some(follow_mode) = self.follow_mode {
+            options.follow_mode = follow_mode;
+        }
+        if let Some(generation_markers) = self.generation_markers {
+            options.generation_markers = generation_markers;
+        }
+        if let Some(change_fade) = self.change_fade {
+            options.change_fade = change_fade;
+        }
+        if let Some(history) = self.history {
+            options.history = history;
+        }
+        if let Some(diff_split) = self.diff_split {
+            options.diff_split = diff_split;
+        }
+    }
+}
+
+pub fn parse_tab_width(value: &str) -> Result<usize> {
+    match value.parse::<usize>() {
+        Ok(width) if (1..=32).contains(&width) => Ok(width),
+        _ => Err(MorelError::Usage(format!(
+            "invalid tab width '{}' (expected 1 to 32)",
+            value
+        ))),
+    }
+}
+
+/// Parses an encoding name, where `auto` means detect it.
+pub fn parse_encoding(value: &str) -> Result<Option<Encoding>> {
+    if value == "auto" {
+        return Ok(None);
+    }
+    Encoding::from_name(value).map(Some).ok_or_else(|| {
+        MorelError::Usage(format!(
+            "unknown encoding '{}' (expected auto, utf-8, utf-16le, utf-16be, latin1 or windows-1252)",
+            value
+        ))
+    })
+}
+
+pub fn parse_debounce(value: &str) -> Result<Duration> {
+    match value.parse::<u64>() {
+        Ok(ms) if ms > 0 => Ok(Duration::from_millis(ms)),
+        _ => Err(MorelError::Usage(format!(
+            "invalid debounce interval '{}' (expected milliseconds greater than 0)",
+            value
+        ))),
+    }
+}
+
+pub fn parse_change_fade(value: &str) -> Result<Duration> {
+    value.parse::<u64>().map(Duration::from_secs).map_err(|_| {
+        MorelError::Usage(format!(
+            "invalid change fade time '{}' (expected whole seconds)",
+            value
+        ))
+    })
+}
+
+pub fn parse_history(value: &str) -> Result<usize> {
+    value.parse::<usize>().map_err(|_| {
+        MorelError::Usage(format!(
+            "invalid history size '{}' (expected a number of versions)",
+            value
+        ))
+    })
+}
+
+/// Parses a diff layout, returning whether it is side by side.
+pub fn parse_diff_layout(value: &str) -> Result<bool> {
+    match value {
+        "split" => Ok(true),
+        "inline" => Ok(false),
+        _ => Err(MorelError::Usage(format!(
+            "invalid diff layout '{}' (expected 'split' or 'inline')",
+            value
+        ))),
+    }
+}
+
+pub fn parse_watcher_backend(value: &str) -> Result<WatcherBackend> {
+    match value {
+        "native" => Ok(WatcherBackend::Native),
+        "poll" => Ok(WatcherBackend::Poll),
+        _ => Err(MorelError::Usage(format!(
+            "invalid watcher backend '{}' (expected 'native' or 'poll')",
+            value
+        ))),
+    }
+}
+
+pub fn parse_follow_mode(value: &str) -> Result<FollowMode> {
+    match value {
+        "name" => Ok(FollowMode::Name),
+        "descriptor" => Ok(FollowMode::Descriptor),
+        _ => Err(MorelError::Usage(format!(
+            "invalid follow mode '{}' (expected 'name' or 'descriptor')",
+            value
+        ))),
+    }
+}
+
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "yes" | "on" => Some(true),
+        "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliAction> {
+        Cli::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    /// The options `args` give on top of the defaults, and the file.
+    fn options(args: &[&str]) -> (Options, Option<String>) {
+        let Ok(CliAction::Run(cli)) = parse(args) else {
+            panic!("{:?} did not parse", args);
+        };
+        let mut options = Options::default();
+        cli.apply_to(&mut options);
+        (options, cli.file)
+    }
+
+    fn usage_error(args: &[&str]) -> String {
+        match parse(args) {
+            Err(MorelError::Usage(message)) => message,
+            Err(e) => panic!("{:?} gave {}", args, e),
+            Ok(_) => panic!("{:?} parsed", args),
+        }
+    }
+
+    #[test]
+    fn no_flags_leave_the_defaults() {
+        let (options, file) = options(&[]);
+        assert_eq!(file, None);
+        assert_eq!(options.start, StartPosition::Top);
+        assert!(options.wrap && !options.follow && !options.line_numbers);
+        assert_eq!(options.tab_width, 8);
+        assert_eq!(options.encoding, None);
+    }
+
+    #[test]
+    fn flags_set_options() {
+        let (options, file) = options(&[
+            "-N",
+            "-S",
+            "-R",
+            "-x",
+            "4",
+            "--encoding=latin1",
+            "--debounce",
+            "250",
+            "--watcher=poll",
+            "--follow-by",
+            "descriptor",
+            "--history",
+            "3",
+            "--diff-layout=inline",
+            "+F",
+            "app.log",
+        ]);
+        assert_eq!(file.as_deref(), Some("app.log"));
+        assert!(options.line_numbers && !options.wrap && options.raw_control_chars);
+        assert!(options.follow);
+        assert_eq!(options.tab_width, 4);
+        assert_eq!(options.encoding, Some(Encoding::Latin1));
+        assert_eq!(options.debounce, Duration::from_millis(250));
+        assert_eq!(options.watcher_backend, WatcherBackend::Poll);
+        assert_eq!(options.follow_mode, FollowMode::Descriptor);
+        assert_eq!(options.history, 3);
+        assert!(!options.diff_split);
+    }
+
+    #[test]
+    fn plus_commands_set_where_to_start() {
+        assert_eq!(options(&["+G"]).0.start, StartPosition::End);
+        assert_eq!(options(&["--end"]).0.start, StartPosition::End);
+        assert_eq!(options(&["+120"]).0.start, StartPosition::Line(120));
+        assert_eq!(
+            options(&["+/ERROR"]).0.start,
+            StartPosition::Pattern("ERROR".to_string())
+        );
+    }
+
+    #[test]
+    fn flags_given_override_the_config_and_others_leave_it() {
+        let Ok(CliAction::Run(cli)) = parse(&["-x", "2"]) else {
+            panic!("did not parse");
+        };
+        let mut options = Options {
+            line_numbers: true,
+            tab_width: 4,
+            ..Options::default()
+        };
+        cli.apply_to(&mut options);
+        assert!(options.line_numbers);
+        assert_eq!(options.tab_width, 2);
+    }
+
+    #[test]
+    fn a_dash_is_standard_input_and_a_double_dash_ends_the_flags() {
+        assert_eq!(options(&["-"]).1.as_deref(), Some("-"));
+        assert_eq!(options(&["--", "-x"]).1.as_deref(), Some("-x"));
+    }
+
+    #[test]
+    fn diff_takes_two_files() {
+        let Ok(CliAction::Run(cli)) = parse(&["--diff", "old", "new"]) else {
+            panic!("did not parse");
+        };
+        assert_eq!(cli.diff, Some((PathBuf::from("old"), PathBuf::from("new"))));
+        assert!(usage_error(&["--diff", "old", "new", "other"]).contains("--diff"));
+        assert!(usage_error(&["--diff", "old"]).contains("requires a value"));
+    }
+
+    #[test]
+    fn help_and_version_are_recognised() {
+        assert!(matches!(parse(&["-h"]), Ok(CliAction::Help)));
+        assert!(matches!(parse(&["app.log", "--help"]), Ok(CliAction::Help)));
+        assert!(matches!(parse(&["-V"]), Ok(CliAction::Version)));
+    }
+
+    #[test]
+    fn bad_arguments_are_usage_errors() {
+        assert!(usage_error(&["--bogus"]).contains("unknown option"));
+        assert!(usage_error(&["+12x"]).contains("unknown option"));
+        assert!(usage_error(&["-x"]).contains("requires a value"));
+        assert!(usage_error(&["-x", "0"]).contains("tab width"));
+        assert!(usage_error(&["--encoding", "ebcdic"]).contains("encoding"));
+        assert!(usage_error(&["--debounce", "0"]).contains("debounce"));
+        assert!(usage_error(&["--wait=yes"]).contains("does not take a value"));
+        assert!(usage_error(&["a.log", "b.log"]).contains("one file"));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::cli::{self, Options};
+use crate::error::{MorelError, Result};
+use crate::status::StatusTemplate;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The config file read when `--config` is not given:
+/// `$XDG_CONFIG_HOME/morel/config`, falling back to `~/.config/morel/config`.
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("morel").join("config"))
+}
+
+/// Reads settings from the config file at `path` into `options`.
+///
+/// The file holds one `key = value` setting per line, with `#` starting a
+/// comment. A value may be put in double quotes to keep spaces at its
+/// ends. A missing file is only an error when `required` is set, which is
+/// the case when the path was given explicitly.
+pub fn load(path: &Path, required: bool, options: &mut Options) -> Result<()> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => return Ok(()),
+        Err(e) => {
+            return Err(MorelError::Config(format!("{}: {}", path.display(), e)));
+        }
+    };
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let error = |message: String| {
+            MorelError::Config(format!("{}:{}: {}", path.display(), number + 1, message))
+        };
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error(format!("expected 'key = value', found '{}'", line)))?;
+        let (key, value) = (key.trim(), value.trim());
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+
+        apply_setting(key, value, options).map_err(|e| match e {
+            MorelError::Usage(message) | MorelError::Config(message) => error(message),
+            other => other,
+        })?;
+    }
+
+    Ok(())
+}
+
+fn apply_setting(key: &str, value: &str, options: &mut Options) -> Result<()> {
+    let flag = |value: &str| {
+        cli::parse_bool(value).ok_or_else(|| {
+            MorelError::Config(format!("invalid value '{}' for {} (expected true or false)", value, key))
+        })
+    };
+
+    match key {
+        "follow" => options.follow = flag(value)?,
+        "wait" => options.wait = flag(value)?,
+        "line_numbers" => options.line_numbers = flag(value)?,
+        "wrap" => options.wrap = flag(value)?,
+        "tab_width" => options.tab_width = cli::parse_tab_width(value)?,
+        "raw_control_chars" => options.raw_control_chars = flag(value)?,
+        "encoding" => options.encoding = cli::parse_encoding(value)?,
+        "debounce_ms" => options.debounce = cli::parse_debounce(value)?,
+        "watcher" => options.watcher_backend = cli::parse_watcher_backend(value)?,
+        "follow_by" => options.follow_mode = cli::parse_follow_mode(value)?,
+        "generation_markers" => options.generation_markers = flag(value)?,
+        "change_fade_secs" => options.change_fade = cli::parse_change_fade(value)?,
+        "history" => options.history = cli::parse_history(value)?,
+        "diff_layout" => options.diff_split = cli::parse_diff_layout(value)?,
+        "status_left" => options.status_left = StatusTemplate::parse(value)?,
+        "status_right" => options.status_right = StatusTemplate::parse(value)?,
+        _ => return Err(MorelError::Config(format!("unknown setting '{}'", key))),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,275 @@
+use crate::error::Result;
+use crate::file_reader::FileReader;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+
+/// Edits beyond which two versions are taken to differ throughout the part
+/// between what they start and end with, to bound the time and memory a
+/// diff takes.
+const MAX_EDITS: usize = 1000;
+
+/// Lines hashed per read.
+const HASH_CHUNK_LINES: usize = 4096;
+
+/// A run of lines that differs between two versions: the `old` lines were
+/// replaced by the `new` ones. Either range may be empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// The hunks turning `old` into `new`, in order, found with Myers'
+/// algorithm after setting aside the lines both start and end with.
+pub fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Hunk> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = prefix..old.len() - suffix;
+    let new_middle = prefix..new.len() - suffix;
+    if old_middle.is_empty() && new_middle.is_empty() {
+        return Vec::new();
+    }
+
+    match shortest_edit(&old[old_middle.clone()], &new[new_middle.clone()]) {
+        Some(hunks) => hunks
+            .into_iter()
+            .map(|hunk| Hunk {
+                old: hunk.old.start + prefix..hunk.old.end + prefix,
+                new: hunk.new.start + prefix..hunk.new.end + prefix,
+            })
+            .collect(),
+        None => vec![Hunk {
+            old: old_middle,
+            new: new_middle,
+        }],
+    }
+}
+
+/// Hashes a line so versions can be compared without keeping their text.
+pub fn hash_line(line: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    line.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Hashes of `lines` of `file_reader`'s file, which must have been
+/// indexed that far.
+pub fn hash_lines(file_reader: &mut FileReader, lines: Range<usize>) -> Result<Vec<u64>> {
+    let mut hashes = Vec::with_capacity(lines.len());
+    for start in lines.clone().step_by(HASH_CHUNK_LINES) {
+        let count = HASH_CHUNK_LINES.min(lines.end - start);
+        let texts = file_reader.get_lines(start, count)?;
+        hashes.extend(texts.iter().map(|text| hash_line(text)));
+    }
+    Ok(hashes)
+}
+
+/// The hunks of a shortest edit from `a` to `b`, or `None` if it takes more
+/// than `MAX_EDITS` insertions and deletions.
+fn shortest_edit<T: PartialEq>(a: &[T], b: &[T]) -> Option<Vec<Hunk>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m).min(MAX_EDITS as isize);
+    let offset = max + 1;
+    let index = |k: isize| (offset + k) as usize;
+
+    // `v[k]` is how far along `a` the furthest path on diagonal `k` got;
+    // its state before each round is kept to trace the path back
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    let mut trace = Vec::new();
+    let mut reached = false;
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+                v[index(k + 1)]
+            } else {
+                v[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index(k)] = x;
+            if x >= n && y >= m {
+                reached = true;
+                break 'search;
+            }
+        }
+    }
+    if !reached {
+        return None;
+    }
+
+    // Walk back from the end, noting which lines were deleted and inserted
+    let mut deleted = vec![false; a.len()];
+    let mut inserted = vec![false; b.len()];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let previous_k = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = v[index(previous_k)];
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == previous_x {
+                inserted[previous_y as usize] = true;
+            } else {
+                deleted[previous_x as usize] = true;
+            }
+        }
+        x = previous_x;
+        y = previous_y;
+    }
+
+    // Lines neither deleted nor inserted pair up in order; whatever lies
+    // between two pairs makes a hunk
+    let mut hunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && !deleted[i] && !inserted[j] {
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let (old_start, new_start) = (i, j);
+        loop {
+            let before = (i, j);
+            while i < a.len() && deleted[i] {
+                i += 1;
+            }
+            while j < b.len() && inserted[j] {
+                j += 1;
+            }
+            if (i, j) == before {
+                break;
+            }
+        }
+        if (i, j) == (old_start, new_start) {
+            break;
+        }
+        hunks.push(Hunk {
+            old: old_start..i,
+            new: new_start..j,
+        });
+    }
+    Some(hunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Length of the longest common subsequence, the number of lines a
+    /// shortest edit leaves alone.
+    fn lcs_len(a: &[u8], b: &[u8]) -> usize {
+        let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                table[i][j] = if a[i] == b[j] {
+                    table[i + 1][j + 1] + 1
+                } else {
+                    table[i + 1][j].max(table[i][j + 1])
+                };
+            }
+        }
+        table[0][0]
+    }
+
+    /// Applies `hunks` to `old`, taking the replacement lines from `new`.
+    fn apply(old: &[u8], new: &[u8], hunks: &[Hunk]) -> Vec<u8> {
+        let mut result = Vec::new();
+        let mut i = 0;
+        for hunk in hunks {
+            result.extend_from_slice(&old[i..hunk.old.start]);
+            result.extend_from_slice(&new[hunk.new.clone()]);
+            i = hunk.old.end;
+        }
+        result.extend_from_slice(&old[i..]);
+        result
+    }
+
+    /// Short sequences over a small alphabet, so they share plenty of lines.
+    fn sequences() -> Vec<Vec<u8>> {
+        let mut state = 0x2545_f491_u32;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        (0..150)
+            .map(|_| {
+                let len = next() % 12;
+                (0..len).map(|_| b"abcd"[(next() % 4) as usize]).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hunks_are_a_shortest_edit() {
+        let sequences = sequences();
+        for old in &sequences {
+            for new in sequences.iter().take(40) {
+                let hunks = diff(old, new);
+                assert_eq!(apply(old, new, &hunks), *new, "{:?} -> {:?}", old, new);
+
+                let changed: usize = hunks.iter().map(|hunk| hunk.old.len()).sum();
+                assert_eq!(old.len() - changed, lcs_len(old, new), "{:?} -> {:?}", old, new);
+            }
+        }
+    }
+
+    #[test]
+    fn hunks_are_in_order_apart_and_never_empty() {
+        let sequences = sequences();
+        for old in &sequences {
+            for new in sequences.iter().take(40) {
+                let hunks = diff(old, new);
+                for hunk in &hunks {
+                    assert!(!hunk.old.is_empty() || !hunk.new.is_empty());
+                }
+                // Hunks next to each other would have been one
+                for pair in hunks.windows(2) {
+                    assert!(pair[0].old.end < pair[1].old.start);
+                    assert!(pair[0].new.end < pair[1].new.start);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn identical_sequences_have_no_hunks() {
+        assert!(diff(b"abc", b"abc").is_empty());
+        assert!(diff::<u8>(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn too_many_edits_make_one_hunk_between_the_common_ends() {
+        let old: Vec<u32> = (0..2000).collect();
+        let mut new: Vec<u32> = (5000..7000).collect();
+        new[0] = 0;
+        *new.last_mut().unwrap() = 1999;
+        assert_eq!(
+            diff(&old, &new),
+            [Hunk {
+                old: 1..1999,
+                new: 1..1999,
+            }]
+        );
+    }
+}
@@ -0,0 +1,586 @@
+use crate::ansi::Style;
+use crate::cli::Options;
+use crate::diff::{self, Hunk};
+use crate::encoding::Encoding;
+use crate::error::Result;
+use crate::file_reader::{FileReader, FollowMode, ReloadResult};
+use crate::input::{Command, InputHandler};
+use crate::layout;
+use crate::status::{StatusLine, StatusSpan};
+use crate::terminal::{DiffMark, DiffPane, Terminal};
+use crate::watcher::{FileEvent, FileWatcher, WatcherBackend};
+use std::path::Path;
+use std::time::Duration;
+
+/// Settings the diff view takes from the pager's options.
+#[derive(Debug, Clone, Copy)]
+pub struct DiffSettings {
+    /// Whether the sides are shown next to each other rather than inline
+    pub split: bool,
+    pub tab_width: usize,
+    pub encoding: Option<Encoding>,
+    pub debounce: Duration,
+    pub watcher_backend: WatcherBackend,
+}
+
+impl DiffSettings {
+    pub fn from_options(options: &Options) -> Self {
+        Self {
+            split: options.diff_split,
+            tab_width: options.tab_width,
+            encoding: options.encoding,
+            debounce: options.debounce,
+            watcher_backend: options.watcher_backend,
+        }
+    }
+}
+
+/// One of the two files being compared, watched for changes unless it is
+/// a stored version that never changes.
+pub struct DiffSide {
+    name: String,
+    reader: FileReader,
+    watcher: Option<FileWatcher>,
+    /// Hashes of every line, which the diff is worked out from
+    hashes: Vec<u64>,
+}
+
+impl DiffSide {
+    /// Opens the file at `path`, shown as `name`. A side that is watched
+    /// is compared again whenever it changes.
+    pub fn open(
+        name: String,
+        path: &Path,
+        encoding: Option<Encoding>,
+        watch: bool,
+        settings: &DiffSettings,
+    ) -> Result<Self> {
+        let reader = FileReader::new(path, encoding, FollowMode::Name, false)?;
+        let watcher = if watch {
+            Some(FileWatcher::new(path, settings.debounce, settings.watcher_backend)?)
+        } else {
+            None
+        };
+        let mut side = Self {
+            name,
+            reader,
+            watcher,
+            hashes: Vec::new(),
+        };
+        side.rehash()?;
+        Ok(side)
+    }
+
+    fn rehash(&mut self) -> Result<()> {
+        self.reader.index_all()?;
+        let end = self.reader.total_lines();
+        self.hashes = diff::hash_lines(&mut self.reader, 0..end)?;
+        Ok(())
+    }
+
+    /// Reads the file again if it changed, returning how.
+    fn check_for_changes(&mut self) -> Result<ReloadResult> {
+        let Some(watcher) = self.watcher.as_mut() else {
+            return Ok(ReloadResult::NoChange);
+        };
+        if watcher.check_for_changes()? == FileEvent::NoChange {
+            return Ok(ReloadResult::NoChange);
+        }
+        let result = self.reader.reload()?;
+        if !matches!(result, ReloadResult::NoChange | ReloadResult::Deleted) {
+            self.rehash()?;
+        }
+        Ok(result)
+    }
+
+    fn line(&mut self, line: usize) -> Result<(usize, String)> {
+        let text = self.reader.get_lines(line, 1)?.pop().unwrap_or_default();
+        Ok((line, text))
+    }
+}
+
+/// A screen row of the diff: the line of each side shown on it, and
+/// whether it is part of a hunk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Row {
+    left: Option<usize>,
+    right: Option<usize>,
+    changed: bool,
+}
+
+/// Two files compared line by line, scrolled together, and compared again
+/// whenever either changes.
+pub struct DiffView {
+    left: DiffSide,
+    right: DiffSide,
+    split: bool,
+    tab_width: usize,
+    hunks: Vec<Hunk>,
+    rows: Vec<Row>,
+    /// Row each hunk starts at
+    hunk_rows: Vec<usize>,
+    top: usize,
+    /// Columns scrolled off the left edge of each side
+    left_column: usize,
+    terminal_width: u16,
+    terminal_height: u16,
+    message: Option<String>,
+    needs_redraw: bool,
+}
+
+impl DiffView {
+    /// Compares `left` with `right` on a terminal of the given size.
+    pub fn new(
+        left: DiffSide,
+        right: DiffSide,
+        settings: &DiffSettings,
+        terminal_width: u16,
+        terminal_height: u16,
+    ) -> Self {
+        let mut view = Self {
+            left,
+            right,
+            split: settings.split,
+            tab_width: settings.tab_width,
+            hunks: Vec::new(),
+            rows: Vec::new(),
+            hunk_rows: Vec::new(),
+            top: 0,
+            left_column: 0,
+            terminal_width,
+            terminal_height,
+            message: None,
+            needs_redraw: true,
+        };
+        view.compare(false);
+        if view.hunks.is_empty() {
+            view.message = Some("[No differences]".to_string());
+        }
+        view
+    }
+
+    /// Works out the hunks between the sides again, keeping the lines at
+    /// the top of the screen there. `left_changed` says whether the left
+    /// side is among those that changed.
+    fn compare(&mut self, left_changed: bool) {
+        self.hunks = diff::diff(&self.left.hashes, &self.right.hashes);
+        self.build_rows(left_changed);
+    }
+
+    /// Lays the hunks out as screen rows again, keeping the lines at the
+    /// top of the screen there.
+    fn build_rows(&mut self, left_changed: bool) {
+        let top_row = self.rows.get(self.top).copied();
+        let (old_len, new_len) = (self.left.hashes.len(), self.right.hashes.len());
+        (self.rows, self.hunk_rows) = layout_rows(&self.hunks, old_len, new_len, self.split);
+        if let Some(top_row) = top_row {
+            self.top = row_reaching(&self.rows, top_row, left_changed);
+        }
+        self.needs_redraw = true;
+    }
+
+    /// Runs the view until it is closed.
+    pub fn run(&mut self, terminal: &mut Terminal, input_handler: &mut InputHandler) -> Result<()> {
+        loop {
+            self.check_for_changes()?;
+
+            if self.needs_redraw {
+                self.render(terminal)?;
+                self.needs_redraw = false;
+            }
+
+            let command = input_handler.read_command(Duration::from_millis(50))?;
+            match command {
+                Command::Quit => return Ok(()),
+                Command::ScrollDownPage => self.scroll_to(self.top + self.height()),
+                Command::ScrollDownLine => self.scroll_to(self.top + 1),
+                Command::ScrollUpPage => self.scroll_to(self.top.saturating_sub(self.height())),
+                Command::ScrollUpLine => self.scroll_to(self.top.saturating_sub(1)),
+                Command::JumpToStart => self.scroll_to(0),
+                Command::JumpToEnd => self.scroll_to(usize::MAX),
+                Command::ScrollLeft(columns) => {
+                    let columns = columns.unwrap_or(self.pane_width() / 2);
+                    self.left_column = self.left_column.saturating_sub(columns);
+                    self.needs_redraw = true;
+                }
+                Command::ScrollRight(columns) => {
+                    // Stop once the end of the widest line on screen is in view
+                    let pane_width = self.pane_width();
+                    let columns = columns.unwrap_or(pane_width / 2);
+                    let limit = self
+                        .widest_visible_line()?
+                        .saturating_sub(pane_width)
+                        .max(self.left_column);
+                    self.left_column = (self.left_column + columns).min(limit);
+                    self.needs_redraw = true;
+                }
+                Command::NextChange => {
+                    match self.hunk_rows.iter().find(|&&row| row > self.top) {
+                        Some(&row) => self.scroll_to(row),
+                        None => self.message = Some("[No more changes below]".to_string()),
+                    }
+                    self.needs_redraw = true;
+                }
+                Command::PreviousChange => {
+                    match self.hunk_rows.iter().rev().find(|&&row| row < self.top) {
+                        Some(&row) => self.scroll_to(row),
+                        None => self.message = Some("[No more changes above]".to_string()),
+                    }
+                    self.needs_redraw = true;
+                }
+                Command::ToggleSplit => {
+                    self.split = !self.split;
+                    self.build_rows(false);
+                }
+                Command::Resize(width, height) => {
+                    (self.terminal_width, self.terminal_height) = (width, height);
+                    self.needs_redraw = true;
+                }
+                Command::PromptChanged => self.needs_redraw = true,
+                _ => {}
+            }
+        }
+    }
+
+    /// Compares the sides again if either has changed.
+    fn check_for_changes(&mut self) -> Result<()> {
+        let mut message = None;
+        let mut left_changed = false;
+        for (k, side) in [&mut self.left, &mut self.right].into_iter().enumerate() {
+            match side.check_for_changes()? {
+                ReloadResult::NoChange => {}
+                ReloadResult::Deleted => message = Some(format!("[{} deleted]", side.name)),
+                _ => {
+                    message = Some(format!("[{} changed]", side.name));
+                    left_changed |= k == 0;
+                }
+            }
+        }
+        if message.is_some() {
+            self.message = message;
+            self.compare(left_changed);
+        }
+        Ok(())
+    }
+
+    /// Scrolls so `row` is at the top, as far as the last row allows.
+    fn scroll_to(&mut self, row: usize) {
+        self.top = row.min(self.rows.len().saturating_sub(self.height()));
+        self.message = None;
+        self.needs_redraw = true;
+    }
+
+    /// Rows of the screen left for the diff above the status line.
+    fn height(&self) -> usize {
+        (self.terminal_height as usize).saturating_sub(1).max(1)
+    }
+
+    /// Columns of text each side has room for, between its line numbers
+    /// and the separator.
+    fn pane_width(&self) -> usize {
+        let panes = if self.split { 2 } else { 1 };
+        let pane = (self.terminal_width as usize).saturating_sub(panes - 1) / panes;
+        pane.saturating_sub(self.number_width() + 2).max(1)
+    }
+
+    /// Display width of the widest line of either side on screen.
+    fn widest_visible_line(&mut self) -> Result<usize> {
+        let height = self.height();
+        let rows: Vec<Row> = self.rows.iter().skip(self.top).take(height).copied().collect();
+        let mut widest = 0;
+        for row in rows {
+            let lines = [(&mut self.left, row.left), (&mut self.right, row.right)];
+            for (side, line) in lines {
+                if let Some(line) = line {
+                    let (_, text) = side.line(line)?;
+                    let width = layout::layout(&text, self.tab_width)
+                        .iter()
+                        .map(|cell| cell.width)
+                        .sum();
+                    widest = widest.max(width);
+                }
+            }
+        }
+        Ok(widest)
+    }
+
+    fn number_width(&self) -> usize {
+        let lines = self.left.hashes.len().max(self.right.hashes.len()).max(1);
+        lines.to_string().len()
+    }
+
+    fn render(&mut self, terminal: &mut Terminal) -> Result<()> {
+        let height = self.height();
+        self.top = self.top.min(self.rows.len().saturating_sub(height));
+
+        let mut screen = Vec::with_capacity(height);
+        for row in self.rows.iter().skip(self.top).take(height).copied() {
+            let mark = |removed| match (row.changed, removed) {
+                (false, _) => DiffMark::Same,
+                (true, true) => DiffMark::Removed,
+                (true, false) => DiffMark::Added,
+            };
+            let left = row.left.map(|line| self.left.line(line)).transpose()?;
+            let right = row.right.map(|line| self.right.line(line)).transpose()?;
+            let panes = if self.split {
+                vec![
+                    DiffPane {
+                        line: left,
+                        mark: mark(true),
+                    },
+                    DiffPane {
+                        line: right,
+                        mark: mark(false),
+                    },
+                ]
+            } else {
+                // A line both sides share is shown once, numbered as in the
+                // newer side
+                let removed = right.is_none();
+                vec![DiffPane {
+                    line: right.or(left),
+                    mark: mark(removed),
+                }]
+            };
+            screen.push(panes);
+        }
+
+        let status = self.status_line(height);
+        terminal.render_diff(
+            &screen,
+            self.number_width(),
+            self.left_column,
+            self.tab_width,
+            &status,
+            self.message.as_deref(),
+        )
+    }
+
+    fn status_line(&self, height: usize) -> StatusLine {
+        let span = |text: String| StatusSpan {
+            text,
+            style: Style::default(),
+        };
+        let place = self.hunk_rows.iter().filter(|&&row| row <= self.top).count();
+        let bottom = (self.top + height).min(self.rows.len());
+        StatusLine {
+            left: vec![span(format!(
+                "--Diff-- {} → {}  change {}/{}",
+                self.left.name,
+                self.right.name,
+                place,
+                self.hunk_rows.len()
+            ))],
+            right: vec![span(format!(
+                "rows {}-{}/{}",
+                (self.top + 1).min(bottom),
+                bottom,
+                self.rows.len()
+            ))],
+        }
+    }
+}
+
+/// Lays `hunks` between `old_len` and `new_len` lines out as screen rows:
+/// removed and added lines across from each other when `split`, or one
+/// after the other inline. Also returns the row each hunk starts at.
+fn layout_rows(
+    hunks: &[Hunk],
+    old_len: usize,
+    new_len: usize,
+    split: bool,
+) -> (Vec<Row>, Vec<usize>) {
+    let mut rows = Vec::new();
+    let mut hunk_rows = Vec::new();
+
+    let (mut i, mut j) = (0, 0);
+    for hunk in hunks.iter().chain(std::iter::once(&Hunk {
+        old: old_len..old_len,
+        new: new_len..new_len,
+    })) {
+        while i < hunk.old.start && j < hunk.new.start {
+            rows.push(Row {
+                left: Some(i),
+                right: Some(j),
+                changed: false,
+            });
+            i += 1;
+            j += 1;
+        }
+        if hunk.old.is_empty() && hunk.new.is_empty() {
+            break;
+        }
+
+        hunk_rows.push(rows.len());
+        if split {
+            for k in 0..hunk.old.len().max(hunk.new.len()) {
+                rows.push(Row {
+                    left: Some(hunk.old.start + k).filter(|line| hunk.old.contains(line)),
+                    right: Some(hunk.new.start + k).filter(|line| hunk.new.contains(line)),
+                    changed: true,
+                });
+            }
+        } else {
+            rows.extend(hunk.old.clone().map(|line| Row {
+                left: Some(line),
+                right: None,
+                changed: true,
+            }));
+            rows.extend(hunk.new.clone().map(|line| Row {
+                left: None,
+                right: Some(line),
+                changed: true,
+            }));
+        }
+        (i, j) = (hunk.old.end, hunk.new.end);
+    }
+    (rows, hunk_rows)
+}
+
+/// The first of `rows` reaching the line of `top`, the row that was at the
+/// top of the screen before the rows were laid out again. The line is
+/// looked for on the right side if `left_changed`, and on the left one
+/// otherwise, since lines of a side that changed may have moved. A row
+/// without a line on that side is found by its line on the other.
+fn row_reaching(rows: &[Row], top: Row, left_changed: bool) -> usize {
+    let left: fn(&Row) -> Option<usize> = |row| row.left;
+    let right: fn(&Row) -> Option<usize> = |row| row.right;
+    let (kept, other) = if left_changed { (right, left) } else { (left, right) };
+    let Some((side, line)) = [kept, other]
+        .into_iter()
+        .find_map(|side| side(&top).map(|line| (side, line)))
+    else {
+        return 0;
+    };
+    rows.iter()
+        .position(|row| side(row).is_some_and(|row_line| row_line >= line))
+        .unwrap_or(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn same(left: usize, right: usize) -> Row {
+        Row {
+            left: Some(left),
+            right: Some(right),
+            changed: false,
+        }
+    }
+
+    fn changed(left: Option<usize>, right: Option<usize>) -> Row {
+        Row {
+            left,
+            right,
+            changed: true,
+        }
+    }
+
+    /// The rows of `old` compared with `new`, and the row each hunk
+    /// starts at.
+    fn rows_of(old: &[u64], new: &[u64], split: bool) -> (Vec<Row>, Vec<usize>) {
+        layout_rows(&diff::diff(old, new), old.len(), new.len(), split)
+    }
+
+    #[test]
+    fn split_rows_put_removed_and_added_lines_side_by_side() {
+        let (rows, hunk_rows) = rows_of(&[1, 2, 3, 4], &[1, 5, 6, 7, 4], true);
+        assert_eq!(
+            rows,
+            [
+                same(0, 0),
+                changed(Some(1), Some(1)),
+                changed(Some(2), Some(2)),
+                changed(None, Some(3)),
+                same(3, 4),
+            ]
+        );
+        assert_eq!(hunk_rows, [1]);
+    }
+
+    #[test]
+    fn inline_rows_put_removed_lines_before_added_ones() {
+        let (rows, hunk_rows) = rows_of(&[1, 2, 3, 4], &[1, 5, 6, 7, 4], false);
+        assert_eq!(
+            rows,
+            [
+                same(0, 0),
+                changed(Some(1), None),
+                changed(Some(2), None),
+                changed(None, Some(1)),
+                changed(None, Some(2)),
+                changed(None, Some(3)),
+                same(3, 4),
+            ]
+        );
+        assert_eq!(hunk_rows, [1]);
+    }
+
+    #[test]
+    fn every_hunk_starts_a_row() {
+        let old = [1, 2, 3, 4, 5, 6];
+        let new = [9, 2, 3, 5, 6, 7];
+        let (rows, hunk_rows) = rows_of(&old, &new, false);
+        assert_eq!(hunk_rows, [0, 4, 7]);
+        for &row in &hunk_rows {
+            assert!(rows[row].changed);
+        }
+        assert_eq!(rows.len(), 8);
+
+        let (_, hunk_rows) = rows_of(&old, &new, true);
+        assert_eq!(hunk_rows, [0, 3, 6]);
+    }
+
+    #[test]
+    fn identical_sides_have_no_hunks() {
+        let (rows, hunk_rows) = rows_of(&[1, 2, 3], &[1, 2, 3], true);
+        assert_eq!(rows, [same(0, 0), same(1, 1), same(2, 2)]);
+        assert!(hunk_rows.is_empty());
+        assert_eq!(rows_of(&[], &[], false), (Vec::new(), Vec::new()));
+    }
+
+    #[test]
+    fn the_top_stays_with_the_lines_of_the_side_that_did_not_change() {
+        let same_file = [1, 2, 3, 4, 5, 6, 7, 8];
+        let (rows, _) = rows_of(&same_file, &same_file, false);
+        let top = rows[5];
+
+        // Two lines inserted above the top on the right push it down there
+        let longer = [1, 10, 11, 2, 3, 4, 5, 6, 7, 8];
+        let (rows, _) = rows_of(&same_file, &longer, false);
+        assert_eq!(rows[row_reaching(&rows, top, false)], same(5, 7));
+
+        // and likewise on the left
+        let (rows, _) = rows_of(&longer, &same_file, false);
+        assert_eq!(rows[row_reaching(&rows, top, true)], same(7, 5));
+
+        // Removing the top line on the right leaves it shown as removed
+        let shorter = [1, 2, 3, 4, 5, 7, 8];
+        for split in [false, true] {
+            let (rows, _) = rows_of(&same_file, &shorter, split);
+            let top = row_reaching(&rows, top, false);
+            assert_eq!(rows[top], changed(Some(5), None), "split {}", split);
+        }
+    }
+
+    #[test]
+    fn switching_layouts_keeps_the_top_line() {
+        let (old, new) = ([1, 2, 3, 4], [1, 5, 6, 4]);
+        let (rows, _) = rows_of(&old, &new, false);
+        // The first added line, which has no line on the left
+        let top = rows[3];
+        assert_eq!(top, changed(None, Some(1)));
+
+        let (rows, _) = rows_of(&old, &new, true);
+        assert_eq!(rows[row_reaching(&rows, top, false)], changed(Some(1), Some(1)));
+    }
+
+    #[test]
+    fn a_top_past_the_new_end_goes_to_the_end() {
+        let (rows, _) = rows_of(&[1, 2, 3, 4], &[1, 2, 3, 4], false);
+        let top = rows[3];
+        let (rows, _) = rows_of(&[1, 2], &[1, 2], false);
+        assert_eq!(row_reaching(&rows, top, false), rows.len());
+    }
+}
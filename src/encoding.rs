@@ -0,0 +1,211 @@
+use std::io::{self, BufRead};
+
+/// Bytes examined when guessing the encoding of a file without a BOM.
+const DETECT_SAMPLE_SIZE: usize = 4096;
+
+/// The text encodings morel can decode. Anything that cannot be decoded is
+/// shown as U+FFFD rather than failing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    Windows1252,
+}
+
+/// Windows-1252 characters for bytes 0x80 to 0x9F. The five bytes it leaves
+/// undefined map to the matching C1 control, as browsers do.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Some(Encoding::Windows1252),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin1",
+            Encoding::Windows1252 => "windows-1252",
+        }
+    }
+
+    /// Guesses the encoding from the start of a file, returning it with the
+    /// length of any byte order mark to skip. UTF-16 is recognised by its
+    /// BOM, or by the zero bytes ASCII text has in every other position.
+    /// Everything else is read as UTF-8.
+    pub fn detect(head: &[u8]) -> (Self, u64) {
+        if head.starts_with(b"\xEF\xBB\xBF") {
+            return (Encoding::Utf8, 3);
+        }
+        if head.starts_with(b"\xFF\xFE") {
+            return (Encoding::Utf16Le, 2);
+        }
+        if head.starts_with(b"\xFE\xFF") {
+            return (Encoding::Utf16Be, 2);
+        }
+
+        let sample = &head[..head.len().min(DETECT_SAMPLE_SIZE) & !1];
+        if sample.len() >= 4 {
+            let zeros_at = |parity: usize| {
+                sample.iter().skip(parity).step_by(2).filter(|&&b| b == 0).count()
+            };
+            let units = sample.len() / 2;
+            let (even, odd) = (zeros_at(0), zeros_at(1));
+            if odd * 10 >= units * 9 && even == 0 {
+                return (Encoding::Utf16Le, 0);
+            }
+            if even * 10 >= units * 9 && odd == 0 {
+                return (Encoding::Utf16Be, 0);
+            }
+        }
+
+        (Encoding::Utf8, 0)
+    }
+
+    /// Length of the byte order mark this encoding writes, if `head` starts
+    /// with one.
+    pub fn bom_len(self, head: &[u8]) -> u64 {
+        let bom: &[u8] = match self {
+            Encoding::Utf8 => b"\xEF\xBB\xBF",
+            Encoding::Utf16Le => b"\xFF\xFE",
+            Encoding::Utf16Be => b"\xFE\xFF",
+            Encoding::Latin1 | Encoding::Windows1252 => return 0,
+        };
+        if head.starts_with(bom) {
+            bom.len() as u64
+        } else {
+            0
+        }
+    }
+
+    /// Reads one line, including its terminator, onto the end of `buffer`.
+    /// For UTF-16 a line only ends at a whole newline code unit, so a `0x0A`
+    /// byte inside another character is not mistaken for one.
+    pub fn read_line<R: BufRead>(self, reader: &mut R, buffer: &mut Vec<u8>) -> io::Result<usize> {
+        let start = buffer.len();
+        loop {
+            if reader.read_until(b'\n', buffer)? == 0 {
+                break;
+            }
+            let line = &buffer[start..];
+            let ended = match self {
+                Encoding::Utf16Le => {
+                    // `0A` at an even offset, completed by a `00` byte
+                    if line.len() % 2 == 1 {
+                        let mut next = [0];
+                        let read = reader.read(&mut next)?;
+                        buffer.extend_from_slice(&next[..read]);
+                        read == 0 || next[0] == 0
+                    } else {
+                        false
+                    }
+                }
+                Encoding::Utf16Be => line.len().is_multiple_of(2) && line[line.len() - 2] == 0,
+                Encoding::Utf8 | Encoding::Latin1 | Encoding::Windows1252 => true,
+            };
+            if ended {
+                break;
+            }
+        }
+        Ok(buffer.len() - start)
+    }
+
+    /// Decodes a line, replacing anything invalid with U+FFFD.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let units = bytes.chunks(2).map(|pair| match (self, pair) {
+                    (Encoding::Utf16Le, [low, high]) => u16::from_le_bytes([*low, *high]),
+                    (_, [high, low]) => u16::from_be_bytes([*high, *low]),
+                    // A dangling odd byte can't be decoded
+                    _ => 0xFFFD,
+                });
+                char::decode_utf16(units)
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect()
+            }
+            Encoding::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+            Encoding::Windows1252 => bytes
+                .iter()
+                .map(|&b| match b {
+                    0x80..=0x9F => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+                    _ => b as char,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_byte_order_marks() {
+        assert_eq!(Encoding::detect(b"\xEF\xBB\xBFtext"), (Encoding::Utf8, 3));
+        assert_eq!(Encoding::detect(b"\xFF\xFEt\0"), (Encoding::Utf16Le, 2));
+        assert_eq!(Encoding::detect(b"\xFE\xFF\0t"), (Encoding::Utf16Be, 2));
+    }
+
+    #[test]
+    fn detects_utf16_without_a_bom_by_its_zero_bytes() {
+        assert_eq!(Encoding::detect(b"t\0e\0x\0t\0"), (Encoding::Utf16Le, 0));
+        assert_eq!(Encoding::detect(b"\0t\0e\0x\0t"), (Encoding::Utf16Be, 0));
+        assert_eq!(Encoding::detect(b"plain text"), (Encoding::Utf8, 0));
+        assert_eq!(Encoding::detect(b""), (Encoding::Utf8, 0));
+    }
+
+    #[test]
+    fn utf16le_lines_end_only_at_a_whole_newline() {
+        // `Ċ` is `0A 01`, a `0A` byte that is not a newline
+        let data = b"\x0A\x01a\0\n\0b\0";
+        let mut reader = &data[..];
+        let mut line = Vec::new();
+        assert_eq!(Encoding::Utf16Le.read_line(&mut reader, &mut line).unwrap(), 6);
+        assert_eq!(Encoding::Utf16Le.decode(&line), "Ċa\n");
+
+        line.clear();
+        assert_eq!(Encoding::Utf16Le.read_line(&mut reader, &mut line).unwrap(), 2);
+        assert_eq!(Encoding::Utf16Le.decode(&line), "b");
+    }
+
+    #[test]
+    fn utf16be_lines_end_only_at_a_whole_newline() {
+        // `ੁ` is `0A 41`, a `0A` byte that is not a newline
+        let data = b"\x0A\x41\0a\0\n\0b";
+        let mut reader = &data[..];
+        let mut line = Vec::new();
+        assert_eq!(Encoding::Utf16Be.read_line(&mut reader, &mut line).unwrap(), 6);
+        assert_eq!(Encoding::Utf16Be.decode(&line), "ੁa\n");
+    }
+
+    #[test]
+    fn invalid_bytes_decode_to_replacement_characters() {
+        assert_eq!(Encoding::Utf8.decode(b"ok\xFFok"), "ok\u{FFFD}ok");
+        assert_eq!(Encoding::Utf16Le.decode(b"a\0\0\xD8"), "a\u{FFFD}");
+        assert_eq!(Encoding::Utf16Le.decode(b"a\0b"), "a\u{FFFD}");
+    }
+
+    #[test]
+    fn single_byte_encodings_decode_every_byte() {
+        assert_eq!(Encoding::Latin1.decode(b"caf\xE9 \x80"), "café \u{80}");
+        assert_eq!(Encoding::Windows1252.decode(b"caf\xE9 \x80 \x81"), "café € \u{81}");
+    }
+}
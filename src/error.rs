@@ -16,6 +16,15 @@ pub enum MorelError {
 
     #[error("The file was deleted")]
     FileDeleted,
+
+    #[error("Invalid pattern: {0}")]
+    InvalidPattern(String),
+
+    #[error("{0}\nTry 'morel --help' for more information.")]
+    Usage(String),
+
+    #[error("Config error: {0}")]
+    Config(String),
 }
 
 pub type Result<T> = std::result::Result<T, MorelError>;
@@ -1,25 +1,162 @@
+use crate::block_hash::BLOCK_SIZE;
+use crate::encoding::Encoding;
 use crate::error::{MorelError, Result};
-use std::fs;
-use std::io::{BufRead, BufReader};
+use crate::line_index::LineIndex;
+use chrono::Local;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
+/// Bytes indexed per call to `index_step`, small enough that indexing a
+/// large file in the background never makes the pager feel sluggish.
+const INDEX_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Lines read at a time when searching through the file.
+const SEARCH_BLOCK_LINES: usize = 1024;
+
+/// Bytes kept from the end of the file to check on reload that the content
+/// already indexed is unchanged and the file was only appended to.
+const TAIL_SAMPLE_SIZE: u64 = 4096;
+
+/// Bytes read from the start of the file to detect its encoding, and kept
+/// to notice changes that leave its size and modification time alone.
+const HEAD_SAMPLE_SIZE: u64 = 4096;
+
+/// How the file changed since it was last read, judged by how much of what
+/// was read it still starts with.
+#[derive(Debug, PartialEq)]
 pub enum ReloadResult {
     NoChange,
+    /// Everything read is still there, followed by more
     Appended,
+    /// The file was cut short, and what is left is unchanged
     Truncated,
+    /// The file was emptied and written again from its first line
+    Rewritten,
+    /// The file was changed in place from the given line, 0-based within
+    /// the current file, on
+    Edited { first_line: usize },
+    /// Read again from the start without working out what changed
     Modified,
     Deleted,
+    /// The file came back after being deleted
+    Recreated,
+    /// The path names a new file and the old one has been deleted, as when
+    /// an editor saves by renaming a new copy over the old
+    Replaced,
+    Rotated(Rotation),
+}
+
+/// How a log file was rotated out from under us.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rotation {
+    /// The file was renamed or removed and a new one created in its place,
+    /// which is now being read
+    Reopened,
+    /// The file was copied elsewhere and then emptied in place, as
+    /// `logrotate` does with `copytruncate`
+    CopiedAndTruncated,
+    /// The path no longer names the file we have open, which is still being
+    /// read
+    Detached,
 }
 
+/// What to keep reading when a file is rotated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FollowMode {
+    /// Whatever file has the name, reopening it when it is replaced, like
+    /// `tail -F`
+    Name,
+    /// The file first opened, wherever it is moved, like `tail -f`
+    Descriptor,
+}
+
+/// Device and inode of a file, used to tell whether a path still refers to
+/// the file we have open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileId {
+    dev: u64,
+    ino: u64,
+}
+
+impl FileId {
+    #[cfg(unix)]
+    fn of(metadata: &fs::Metadata) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+        Some(Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn of(_metadata: &fs::Metadata) -> Option<Self> {
+        None
+    }
+}
+
+/// Whether the open `file` has been deleted, leaving only our descriptor.
+#[cfg(unix)]
+fn is_unlinked(file: &File) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    file.metadata().is_ok_and(|metadata| metadata.nlink() == 0)
+}
+
+#[cfg(not(unix))]
+fn is_unlinked(_file: &File) -> bool {
+    false
+}
+
+/// A file that was replaced at the path, kept open so its lines can still
+/// be shown above those of the file that replaced it.
+struct Generation {
+    file: File,
+    encoding: Encoding,
+    index: LineIndex,
+    /// Line shown after the file's own, where the next generation begins
+    marker: String,
+}
+
+impl Generation {
+    fn total_lines(&self) -> usize {
+        self.index.total_lines() + 1
+    }
+}
+
+/// Reads lines on demand from a file through a sparse line index, so only
+/// the lines being displayed are ever held in memory.
 pub struct FileReader {
     path: PathBuf,
-    content: Vec<String>,
+    file: File,
+    file_id: Option<FileId>,
+    /// The encoding asked for, or `None` to detect it
+    requested_encoding: Option<Encoding>,
+    encoding: Encoding,
+    index: LineIndex,
+    tail_sample: Vec<u8>,
+    /// The first `HEAD_SAMPLE_SIZE` bytes
+    head_sample: Vec<u8>,
     last_modified: Option<SystemTime>,
+    follow_mode: FollowMode,
+    /// Whether the path has stopped naming the file we have open
+    detached: bool,
+    /// Whether the file was found to be deleted and has not come back yet
+    deleted: bool,
+    /// Whether files replaced at the path are kept above the new one
+    keep_generations: bool,
+    generations: Vec<Generation>,
+    /// Lines in `generations`, including their markers
+    generation_lines: usize,
 }
 
 impl FileReader {
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        encoding: Option<Encoding>,
+        follow_mode: FollowMode,
+        keep_generations: bool,
+    ) -> Result<Self> {
         let path = path.as_ref().to_path_buf();
 
         if !path.exists() {
@@ -28,70 +165,810 @@ impl FileReader {
             ));
         }
 
-        let content = Self::read_file_lines(&path)?;
-        let last_modified = fs::metadata(&path)
-            .ok()
-            .and_then(|m| m.modified().ok());
+        let file = File::open(&path)?;
+        let metadata = file.metadata()?;
+        let last_modified = metadata.modified().ok();
+        let tail_sample = Self::read_tail_sample(&file, metadata.len())?;
+        let head_sample = Self::read_head_sample(&file, metadata.len())?;
+        let (detected, index) = Self::new_index(&file, metadata.len(), encoding)?;
 
         Ok(Self {
             path,
-            content,
+            file,
+            file_id: FileId::of(&metadata),
+            requested_encoding: encoding,
+            encoding: detected,
+            index,
+            tail_sample,
+            head_sample,
             last_modified,
+            follow_mode,
+            detached: false,
+            deleted: false,
+            keep_generations,
+            generations: Vec::new(),
+            generation_lines: 0,
         })
     }
 
-    fn read_file_lines(path: &Path) -> Result<Vec<String>> {
-        let file = fs::File::open(path)?;
-        let reader = BufReader::new(file);
-        let lines: std::io::Result<Vec<String>> = reader.lines().collect();
-        Ok(lines?)
+    /// Starts a fresh index for `len` bytes of `file`, detecting the encoding
+    /// unless one was requested.
+    fn new_index(
+        mut file: &File,
+        len: u64,
+        requested: Option<Encoding>,
+    ) -> Result<(Encoding, LineIndex)> {
+        let mut head = Vec::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.take(HEAD_SAMPLE_SIZE.min(len)).read_to_end(&mut head)?;
+
+        let (encoding, data_start) = match requested {
+            Some(encoding) => (encoding, encoding.bom_len(&head)),
+            None => Encoding::detect(&head),
+        };
+        Ok((encoding, LineIndex::new(encoding, data_start, len)))
     }
 
+    /// Checks the file for changes. When the file has only grown, the index
+    /// is kept and just the new bytes are scanned. Otherwise the index is
+    /// reset and rebuilt lazily, so callers should `ensure_indexed` the lines
+    /// they are about to show.
+    ///
+    /// The path is compared with the open file by device and inode, so a
+    /// rotated log is reported as such rather than as a truncation. Following
+    /// by name then opens the new file, while following by descriptor keeps
+    /// reading the old one.
     pub fn reload(&mut self) -> Result<ReloadResult> {
-        // Check if file still exists
-        if !self.path.exists() {
-            return Ok(ReloadResult::Deleted);
+        let path_metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => Some(metadata),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let moved = match &path_metadata {
+            Some(metadata) => self.file_id.is_some() && FileId::of(metadata) != self.file_id,
+            None => true,
+        };
+
+        match self.follow_mode {
+            FollowMode::Name => {
+                let Some(metadata) = path_metadata else {
+                    if self.deleted {
+                        return Ok(ReloadResult::NoChange);
+                    }
+                    self.deleted = true;
+                    return Ok(ReloadResult::Deleted);
+                };
+                if self.deleted {
+                    self.deleted = false;
+                    self.open_new_generation("recreated", &metadata)?;
+                    return Ok(ReloadResult::Recreated);
+                }
+                // A new inode is a rotation, unless the old file is gone
+                if moved && is_unlinked(&self.file) {
+                    self.open_new_generation("replaced", &metadata)?;
+                    return Ok(ReloadResult::Replaced);
+                }
+                if moved {
+                    self.open_new_generation("rotated", &metadata)?;
+                    return Ok(ReloadResult::Rotated(Rotation::Reopened));
+                }
+                self.check_contents(&metadata)
+            }
+            FollowMode::Descriptor => {
+                let result = self.check_contents(&self.file.metadata()?)?;
+                // Only a new file at the path is a rotation; with none there
+                // the file was deleted, and is still read through the open
+                // descriptor
+                if path_metadata.is_none() {
+                    if self.deleted {
+                        return Ok(result);
+                    }
+                    self.deleted = true;
+                    self.detached = true;
+                    return Ok(ReloadResult::Deleted);
+                }
+                self.deleted = false;
+                if moved && !self.detached {
+                    self.detached = true;
+                    if matches!(result, ReloadResult::NoChange | ReloadResult::Appended) {
+                        return Ok(ReloadResult::Rotated(Rotation::Detached));
+                    }
+                }
+                Ok(result)
+            }
         }
+    }
+
+    /// Checks for changes like `reload`, but reads the file again from the
+    /// start even if none are found, in case one slipped past the checks.
+    pub fn force_reload(&mut self) -> Result<ReloadResult> {
+        let result = self.reload()?;
+        // A deleted file followed by name is gone, but one followed by
+        // descriptor can still be read
+        if result != ReloadResult::NoChange || (self.deleted && !self.detached) {
+            return Ok(result);
+        }
+        let modified = self.file.metadata()?.modified().ok();
+        self.reindex(modified)?;
+        Ok(ReloadResult::Modified)
+    }
 
-        // Get new metadata
-        let metadata = fs::metadata(&self.path)?;
+    /// Compares the open file, described by `metadata`, with what was read
+    /// from it before. Filesystems with coarse timestamps, and writers that
+    /// put the modification time back, can leave it unchanged, so the size
+    /// and samples of the file's start and end are compared as well.
+    fn check_contents(&mut self, metadata: &fs::Metadata) -> Result<ReloadResult> {
         let new_modified = metadata.modified().ok();
+        let new_len = metadata.len();
+        let old_len = self.index.byte_len();
 
-        // Compare with cached metadata
-        if new_modified == self.last_modified {
+        if new_modified == self.last_modified
+            && new_len == old_len
+            && self.is_tail_unchanged(old_len)?
+            && Self::read_head_sample(&self.file, new_len)? == self.head_sample
+        {
             return Ok(ReloadResult::NoChange);
         }
 
-        // Read new content
-        let old_len = self.content.len();
-        let new_content = Self::read_file_lines(&self.path)?;
-        let new_len = new_content.len();
+        // Appended to: the bytes at both ends of what was read are still
+        // there, and so is the last block hashed. This is checked first so a
+        // growing log isn't read again in full on every change.
+        if new_len > old_len
+            && self.is_tail_unchanged(old_len)?
+            && Self::read_head_sample(&self.file, old_len)? == self.head_sample
+            && self.index.hashes().end_matches(&mut &self.file, new_len)?
+        {
+            self.index.grow_to(new_len);
+            self.tail_sample = Self::read_tail_sample(&self.file, new_len)?;
+            if old_len < HEAD_SAMPLE_SIZE {
+                self.head_sample = Self::read_head_sample(&self.file, new_len)?;
+            }
+            self.last_modified = new_modified;
+            return Ok(ReloadResult::Appended);
+        }
 
-        // Determine change type
-        let result = match old_len.cmp(&new_len) {
-            std::cmp::Ordering::Less => ReloadResult::Appended,
-            std::cmp::Ordering::Greater => ReloadResult::Truncated,
-            std::cmp::Ordering::Equal => ReloadResult::Modified,
+        let prefix = self.common_prefix(new_len)?;
+        let read_len = self.index.hashes().end();
+        let result = if prefix >= read_len && read_len == old_len && new_len == old_len {
+            // Only the modification time changed
+            self.last_modified = new_modified;
+            return Ok(ReloadResult::NoChange);
+        } else if new_len < old_len && self.is_truncation(prefix, new_len)? {
+            ReloadResult::Truncated
+        } else {
+            match self.line_at_offset(prefix)? {
+                0 if self.was_copied_away(old_len) => {
+                    ReloadResult::Rotated(Rotation::CopiedAndTruncated)
+                }
+                0 => ReloadResult::Rewritten,
+                first_line => ReloadResult::Edited { first_line },
+            }
         };
 
-        // Update state
-        self.content = new_content;
-        self.last_modified = new_modified;
-
+        // The content we indexed changed, so start over
+        self.reindex(new_modified)?;
         Ok(result)
     }
 
-    pub fn get_lines(&self, start: usize, count: usize) -> &[String] {
-        let end = (start + count).min(self.content.len());
-        let start = start.min(self.content.len());
-        &self.content[start..end]
+    /// How many bytes from the start of what was read the file, now
+    /// `new_len` long, still has unchanged. This is exact within the head
+    /// sample and otherwise rounded down to a whole block hashed.
+    fn common_prefix(&self, new_len: u64) -> Result<u64> {
+        let prefix = self.index.hashes().common_prefix(&mut &self.file, new_len)?;
+        if prefix as usize >= self.head_sample.len() {
+            return Ok(prefix);
+        }
+        let head = Self::read_head_sample(&self.file, new_len)?;
+        let same = head
+            .iter()
+            .zip(&self.head_sample)
+            .take_while(|(new, old)| new == old)
+            .count();
+        Ok(prefix.max(same as u64))
     }
 
-    pub fn total_lines(&self) -> usize {
-        self.content.len()
+    /// Whether the file, cut down to `new_len` bytes, is what was read up to
+    /// there, given the `prefix` known to be unchanged. Bytes past the last
+    /// whole block hashed can only be checked where they fall within the
+    /// tail sample.
+    fn is_truncation(&self, prefix: u64, new_len: u64) -> Result<bool> {
+        if prefix >= new_len {
+            return Ok(true);
+        }
+        // A prefix within the head sample is exact, and one a whole block
+        // short of the end means that block changed
+        if (prefix as usize) < self.head_sample.len() || prefix + BLOCK_SIZE <= new_len {
+            return Ok(false);
+        }
+        let tail_start = self.index.byte_len() - self.tail_sample.len() as u64;
+        if new_len <= tail_start {
+            return Ok(true);
+        }
+        let sample = Self::read_tail_sample(&self.file, new_len)?;
+        let expected = &self.tail_sample[..(new_len - tail_start) as usize];
+        Ok(sample.ends_with(expected))
+    }
+
+    /// The line that byte `offset` falls in, counted from the last
+    /// checkpoint before it, or the number of lines if it is at the end.
+    /// The file must be unchanged up to `offset`, which may lie past what
+    /// has been indexed when it was found from the head sample.
+    fn line_at_offset(&self, offset: u64) -> Result<usize> {
+        let (mut line, mut start) = self.index.checkpoint_before(offset);
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(start))?;
+        let mut reader = BufReader::new(file);
+        let mut buffer = Vec::new();
+        loop {
+            buffer.clear();
+            let read = self.encoding.read_line(&mut reader, &mut buffer)? as u64;
+            if read == 0 || start + read > offset {
+                return Ok(line);
+            }
+            start += read;
+            line += 1;
+        }
+    }
+
+    /// Opens the file now at the path in place of the one we have, which is
+    /// kept above it behind a marker line when generations are kept. `how`
+    /// says what happened to the old file, for the marker.
+    fn open_new_generation(&mut self, how: &str, metadata: &fs::Metadata) -> Result<()> {
+        let file = File::open(&self.path)?;
+        self.file_id = FileId::of(&file.metadata()?);
+        let old_file = std::mem::replace(&mut self.file, file);
+
+        if self.keep_generations {
+            let mut index = std::mem::replace(
+                &mut self.index,
+                LineIndex::new(self.encoding, 0, 0),
+            );
+            while !index.is_complete() {
+                index.scan(&mut &old_file, INDEX_CHUNK_SIZE)?;
+            }
+            let name = self.path.file_name().unwrap_or_default().to_string_lossy();
+            let generation = Generation {
+                file: old_file,
+                encoding: self.encoding,
+                index,
+                marker: format!(
+                    "──── {} {} at {} ────",
+                    name,
+                    how,
+                    Local::now().format("%Y-%m-%d %H:%M:%S")
+                ),
+            };
+            self.generation_lines += generation.total_lines();
+            self.generations.push(generation);
+        }
+
+        self.reindex(metadata.modified().ok())
     }
 
     pub fn path(&self) -> &Path {
         &self.path
     }
+
+    /// The first line of the file now at the path, after the lines and
+    /// markers of any files it replaced.
+    pub fn generation_start(&self) -> usize {
+        self.generation_lines
+    }
+
+    /// Starts indexing the open file again from the beginning.
+    fn reindex(&mut self, modified: Option<SystemTime>) -> Result<()> {
+        let len = self.file.metadata()?.len();
+        let (encoding, index) = Self::new_index(&self.file, len, self.requested_encoding)?;
+        self.encoding = encoding;
+        self.index = index;
+        self.tail_sample = Self::read_tail_sample(&self.file, len)?;
+        self.head_sample = Self::read_head_sample(&self.file, len)?;
+        self.last_modified = modified;
+        Ok(())
+    }
+
+    /// Whether a file next to ours whose name starts with its name, such as
+    /// `app.log.1`, holds the `old_len` bytes we had read, meaning the file
+    /// was copied there before being truncated.
+    fn was_copied_away(&self, old_len: u64) -> bool {
+        let (Some(dir), Some(name)) = (self.path.parent(), self.path.file_name()) else {
+            return false;
+        };
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let Ok(entries) = fs::read_dir(dir) else {
+            return false;
+        };
+        let name = name.to_string_lossy();
+
+        entries.flatten().any(|entry| {
+            let entry_name = entry.file_name();
+            let entry_name = entry_name.to_string_lossy();
+            if entry_name == name || !entry_name.starts_with(name.as_ref()) {
+                return false;
+            }
+            let Ok(file) = File::open(entry.path()) else {
+                return false;
+            };
+            match file.metadata() {
+                Ok(metadata) if metadata.is_file() && metadata.len() >= old_len => {
+                    Self::read_tail_sample(&file, old_len).is_ok_and(|s| s == self.tail_sample)
+                }
+                _ => false,
+            }
+        })
+    }
+
+    /// Whether the path has stopped naming the file being read, which can
+    /// then only be followed by checking it directly.
+    pub fn is_detached(&self) -> bool {
+        self.detached
+    }
+
+    /// Whether the file has been deleted and not yet created again. When
+    /// following by descriptor it is detached as well, and still read.
+    pub fn is_deleted(&self) -> bool {
+        self.deleted
+    }
+
+    fn read_tail_sample(mut file: &File, len: u64) -> Result<Vec<u8>> {
+        let start = len.saturating_sub(TAIL_SAMPLE_SIZE);
+        let mut sample = Vec::with_capacity((len - start) as usize);
+        file.seek(SeekFrom::Start(start))?;
+        file.take(len - start).read_to_end(&mut sample)?;
+        Ok(sample)
+    }
+
+    fn read_head_sample(mut file: &File, len: u64) -> Result<Vec<u8>> {
+        let mut head = Vec::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.take(HEAD_SAMPLE_SIZE.min(len)).read_to_end(&mut head)?;
+        Ok(head)
+    }
+
+    /// Whether the bytes just before `len` still match the sample taken when
+    /// the file was that long.
+    fn is_tail_unchanged(&self, len: u64) -> Result<bool> {
+        Ok(Self::read_tail_sample(&self.file, len)? == self.tail_sample)
+    }
+
+    /// Indexes the next chunk of the file. Returns true if any new lines
+    /// were found.
+    pub fn index_step(&mut self) -> Result<bool> {
+        let before = self.index.total_lines();
+        self.index.scan(&mut self.file, INDEX_CHUNK_SIZE)?;
+        Ok(self.index.total_lines() != before)
+    }
+
+    /// Indexes the file until `line` is known or the end is reached.
+    pub fn ensure_indexed(&mut self, line: usize) -> Result<()> {
+        while self.total_lines() <= line && !self.index.is_complete() {
+            self.index_step()?;
+        }
+        Ok(())
+    }
+
+    pub fn index_all(&mut self) -> Result<()> {
+        while !self.index.is_complete() {
+            self.index_step()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_fully_indexed(&self) -> bool {
+        self.index.is_complete()
+    }
+
+    /// Bytes in the file as of the last reload.
+    pub fn byte_len(&self) -> u64 {
+        self.index.byte_len()
+    }
+
+    /// Copies the bytes of the file now at the path from `start` to its
+    /// length as of the last reload into `out`.
+    pub fn copy_bytes<W: Write>(&mut self, start: u64, out: &mut W) -> Result<()> {
+        let len = self.byte_len().saturating_sub(start);
+        self.file.seek(SeekFrom::Start(start))?;
+        io::copy(&mut (&self.file).take(len), out)?;
+        Ok(())
+    }
+
+    /// When the file was last modified, as of the last reload.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.last_modified
+    }
+
+    /// Byte offset just past the end of `line` and its newline, or the
+    /// length of the file if `line` has not been indexed. Lines of files
+    /// the current one replaced come before its first byte.
+    pub fn line_end_offset(&mut self, line: usize) -> Result<u64> {
+        let Some(line) = line.checked_sub(self.generation_lines) else {
+            return Ok(0);
+        };
+        let Some((offset, skip)) = self.index.locate(line) else {
+            return Ok(self.index.byte_len());
+        };
+
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(&self.file);
+        let mut buffer = Vec::new();
+        let mut end = offset;
+        for _ in 0..=skip {
+            buffer.clear();
+            let read = self.encoding.read_line(&mut reader, &mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            end += read as u64;
+        }
+
+        Ok(end)
+    }
+
+    pub fn get_lines(&mut self, start: usize, count: usize) -> Result<Vec<String>> {
+        self.ensure_indexed((start + count).saturating_sub(1))?;
+        let end = start + count;
+        let mut lines = Vec::new();
+
+        // Lines of files replaced at the path, each followed by its marker
+        let mut base = 0;
+        for generation in &self.generations {
+            let own_lines = generation.index.total_lines();
+            let from = start.max(base) - base;
+            let to = end.min(base + own_lines + 1).saturating_sub(base);
+            if from < to {
+                let own_to = to.min(own_lines);
+                if from < own_to {
+                    lines.extend(read_lines(
+                        &generation.file,
+                        generation.encoding,
+                        &generation.index,
+                        from,
+                        own_to - from,
+                    )?);
+                }
+                if to > own_lines {
+                    lines.push(generation.marker.clone());
+                }
+            }
+            base += generation.total_lines();
+        }
+
+        if end > base {
+            let from = start.max(base) - base;
+            lines.extend(read_lines(
+                &self.file,
+                self.encoding,
+                &self.index,
+                from,
+                end - base - from,
+            )?);
+        }
+
+        Ok(lines)
+    }
+
+    /// Finds the first line at or after `start` for which `matches` is true.
+    pub fn find_forward<F>(&mut self, start: usize, mut matches: F) -> Result<Option<usize>>
+    where
+        F: FnMut(&str) -> bool,
+    {
+        let mut block_start = start;
+        loop {
+            let lines = self.get_lines(block_start, SEARCH_BLOCK_LINES)?;
+            if lines.is_empty() {
+                return Ok(None);
+            }
+            if let Some(i) = lines.iter().position(|line| matches(line)) {
+                return Ok(Some(block_start + i));
+            }
+            block_start += lines.len();
+        }
+    }
+
+    /// Finds the last line before `end` for which `matches` is true.
+    pub fn find_backward<F>(&mut self, end: usize, mut matches: F) -> Result<Option<usize>>
+    where
+        F: FnMut(&str) -> bool,
+    {
+        let mut block_end = end.min(self.total_lines());
+        while block_end > 0 {
+            let block_start = block_end.saturating_sub(SEARCH_BLOCK_LINES);
+            let lines = self.get_lines(block_start, block_end - block_start)?;
+            if let Some(i) = lines.iter().rposition(|line| matches(line)) {
+                return Ok(Some(block_start + i));
+            }
+            block_end = block_start;
+        }
+        Ok(None)
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Lines indexed so far; this is the full count once
+    /// `is_fully_indexed` returns true.
+    pub fn total_lines(&self) -> usize {
+        self.generation_lines + self.index.total_lines()
+    }
+}
+
+/// Reads up to `count` lines of `file` from `start`, through its `index`.
+fn read_lines(
+    mut file: &File,
+    encoding: Encoding,
+    index: &LineIndex,
+    start: usize,
+    count: usize,
+) -> Result<Vec<String>> {
+    let Some((offset, skip)) = index.locate(start) else {
+        return Ok(Vec::new());
+    };
+    let count = count.min(index.total_lines() - start);
+
+    file.seek(SeekFrom::Start(offset))?;
+    let mut reader = BufReader::new(file);
+    let mut buffer = Vec::new();
+    let mut lines = Vec::with_capacity(count);
+
+    for i in 0..skip + count {
+        buffer.clear();
+        if encoding.read_line(&mut reader, &mut buffer)? == 0 {
+            break;
+        }
+        if i < skip {
+            continue;
+        }
+        lines.push(decode_line(encoding, &buffer));
+    }
+
+    Ok(lines)
+}
+
+/// Decodes a line and strips its terminator, accepting both `\n` and
+/// `\r\n`. Invalid bytes become U+FFFD rather than an error.
+fn decode_line(encoding: Encoding, bytes: &[u8]) -> String {
+    let mut line = encoding.decode(bytes);
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// A file in a directory of its own, so nothing else sits next to it.
+    struct TestFile {
+        dir: TempDir,
+    }
+
+    impl TestFile {
+        fn new(content: &str) -> Self {
+            let file = Self {
+                dir: TempDir::new().unwrap(),
+            };
+            file.write(content);
+            file
+        }
+
+        fn path(&self) -> PathBuf {
+            self.dir.path().join("app.log")
+        }
+
+        /// Rewrites the file in place, keeping its inode.
+        fn write(&self, content: &str) {
+            fs::write(self.path(), content).unwrap();
+        }
+
+        fn append(&self, content: &str) {
+            let mut file = fs::OpenOptions::new().append(true).open(self.path()).unwrap();
+            file.write_all(content.as_bytes()).unwrap();
+        }
+
+        fn open(&self, follow_mode: FollowMode) -> FileReader {
+            let mut reader = FileReader::new(self.path(), None, follow_mode, false).unwrap();
+            reader.index_all().unwrap();
+            reader
+        }
+    }
+
+    fn all_lines(reader: &mut FileReader) -> Vec<String> {
+        reader.index_all().unwrap();
+        let total = reader.total_lines();
+        reader.get_lines(0, total).unwrap()
+    }
+
+    fn numbered(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("line {:05}", i)).collect()
+    }
+
+    fn joined(lines: &[String]) -> String {
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    #[test]
+    fn an_unchanged_file_is_no_change() {
+        let file = TestFile::new("one\ntwo\n");
+        let mut reader = file.open(FollowMode::Name);
+        assert_eq!(reader.reload().unwrap(), ReloadResult::NoChange);
+    }
+
+    #[test]
+    fn growing_is_an_append() {
+        let file = TestFile::new("one\ntwo\n");
+        let mut reader = file.open(FollowMode::Name);
+        file.append("three\n");
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Appended);
+        assert_eq!(all_lines(&mut reader), ["one", "two", "three"]);
+    }
+
+    #[test]
+    fn an_edit_before_an_append_is_not_an_append() {
+        let mut lines = numbered(2000);
+        let file = TestFile::new(&joined(&lines));
+        let mut reader = file.open(FollowMode::Name);
+
+        // Join two lines in the middle, leaving every byte after them where
+        // it was, then append
+        let merged = format!("{} {}", lines[1030], lines[1031]);
+        lines.splice(1030..1032, [merged]);
+        lines.push("extra".to_string());
+        file.write(&joined(&lines));
+
+        // The unchanged part is only known to within the head sample here,
+        // so the edit may be placed before the line that changed
+        let result = reader.reload().unwrap();
+        assert!(
+            matches!(result, ReloadResult::Edited { first_line } if first_line <= 1030),
+            "{:?}",
+            result
+        );
+        assert_eq!(all_lines(&mut reader), lines);
+    }
+
+    #[test]
+    fn cutting_the_end_off_is_a_truncation() {
+        let file = TestFile::new("one\ntwo\nthree\n");
+        let mut reader = file.open(FollowMode::Name);
+        file.write("one\ntwo\n");
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Truncated);
+        assert_eq!(all_lines(&mut reader), ["one", "two"]);
+    }
+
+    #[test]
+    fn a_change_in_the_middle_is_an_edit_from_its_line() {
+        let file = TestFile::new("one\ntwo\nthree\n");
+        let mut reader = file.open(FollowMode::Name);
+        file.write("one\nTWO\nthree\n");
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Edited { first_line: 1 });
+        assert_eq!(all_lines(&mut reader), ["one", "TWO", "three"]);
+    }
+
+    #[test]
+    fn an_edit_is_found_before_the_file_is_indexed_again() {
+        let file = TestFile::new("one\ntwo\nthree\n");
+        let mut reader = file.open(FollowMode::Name);
+        file.write("");
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Truncated);
+        file.write("one\ntwo\n");
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Appended);
+        file.write("one\nTWO\n");
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Edited { first_line: 1 });
+    }
+
+    #[test]
+    fn a_change_on_the_first_line_is_a_rewrite() {
+        let file = TestFile::new("one\ntwo\n");
+        let mut reader = file.open(FollowMode::Name);
+        file.write("uno\ndos\ntres\n");
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Rewritten);
+        assert_eq!(all_lines(&mut reader), ["uno", "dos", "tres"]);
+    }
+
+    #[test]
+    fn a_same_length_rewrite_is_found_without_a_new_modification_time() {
+        let file = TestFile::new("one\ntwo\n");
+        let mut reader = file.open(FollowMode::Name);
+        let modified = fs::metadata(file.path()).unwrap().modified().unwrap();
+        file.write("one\nTwo\n");
+        File::options()
+            .write(true)
+            .open(file.path())
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Edited { first_line: 1 });
+    }
+
+    // Files are told apart by inode, and open files can be deleted
+    #[cfg(unix)]
+    #[test]
+    fn a_renamed_file_replaced_by_a_new_one_is_reopened() {
+        let file = TestFile::new("old\n");
+        let mut reader = file.open(FollowMode::Name);
+        fs::rename(file.path(), file.dir.path().join("app.log.1")).unwrap();
+        file.write("new\n");
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Rotated(Rotation::Reopened));
+        assert_eq!(all_lines(&mut reader), ["new"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_new_file_renamed_over_the_old_one_replaces_it() {
+        let file = TestFile::new("old\n");
+        let mut reader = file.open(FollowMode::Name);
+        let new = file.dir.path().join("app.log.tmp");
+        fs::write(&new, "new\n").unwrap();
+        fs::rename(&new, file.path()).unwrap();
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Replaced);
+        assert_eq!(all_lines(&mut reader), ["new"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_file_copied_away_and_truncated_is_a_rotation() {
+        let file = TestFile::new("old\nlines\n");
+        let mut reader = file.open(FollowMode::Name);
+        fs::copy(file.path(), file.dir.path().join("app.log.1")).unwrap();
+        file.write("new\n");
+        assert_eq!(
+            reader.reload().unwrap(),
+            ReloadResult::Rotated(Rotation::CopiedAndTruncated)
+        );
+        assert_eq!(all_lines(&mut reader), ["new"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_deleted_file_is_read_again_when_it_comes_back() {
+        let file = TestFile::new("old\n");
+        let mut reader = file.open(FollowMode::Name);
+        fs::remove_file(file.path()).unwrap();
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Deleted);
+        assert_eq!(reader.reload().unwrap(), ReloadResult::NoChange);
+        assert!(reader.is_deleted());
+
+        file.write("new\n");
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Recreated);
+        assert!(!reader.is_deleted());
+        assert_eq!(all_lines(&mut reader), ["new"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn following_by_descriptor_keeps_reading_a_renamed_file() {
+        let file = TestFile::new("old\n");
+        let mut reader = file.open(FollowMode::Descriptor);
+        let moved = file.dir.path().join("app.log.1");
+        fs::rename(file.path(), &moved).unwrap();
+        file.write("new\n");
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Rotated(Rotation::Detached));
+        assert!(reader.is_detached());
+
+        let mut old = fs::OpenOptions::new().append(true).open(&moved).unwrap();
+        old.write_all(b"more\n").unwrap();
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Appended);
+        assert_eq!(all_lines(&mut reader), ["old", "more"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn following_by_descriptor_keeps_reading_a_deleted_file() {
+        let file = TestFile::new("old\n");
+        let mut reader = file.open(FollowMode::Descriptor);
+        let mut open = fs::OpenOptions::new().append(true).open(file.path()).unwrap();
+        fs::remove_file(file.path()).unwrap();
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Deleted);
+        assert!(reader.is_deleted());
+
+        open.write_all(b"more\n").unwrap();
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Appended);
+        assert_eq!(all_lines(&mut reader), ["old", "more"]);
+    }
 }
@@ -0,0 +1,216 @@
+use crate::ansi;
+use crate::error::Result;
+use crate::file_reader::FileReader;
+use crate::search;
+use regex::Regex;
+
+/// Lines checked against the filter at a time.
+const FILTER_BLOCK_LINES: usize = 4096;
+
+/// Shows only the lines matching a pattern, or only those not matching it.
+///
+/// The file is checked a block at a time, so a filter over a large file
+/// fills in between key presses, and lines appended later are checked as
+/// they arrive. The view's lines are numbered within the filter, and
+/// `file_line` maps them back to lines of the file.
+pub struct Filter {
+    regex: Regex,
+    inverted: bool,
+    raw_control_chars: bool,
+    /// File lines that pass, in order
+    lines: Vec<usize>,
+    /// File lines checked so far
+    scanned: usize,
+}
+
+impl Filter {
+    /// Filters on `pattern`, or keeps the lines not matching it when
+    /// `inverted`. With `raw_control_chars`, escape sequences are left out
+    /// of the text matched.
+    pub fn new(pattern: &str, inverted: bool, raw_control_chars: bool) -> Result<Self> {
+        Ok(Self {
+            regex: search::compile(pattern)?,
+            inverted,
+            raw_control_chars,
+            lines: Vec::new(),
+            scanned: 0,
+        })
+    }
+
+    /// The filter as it would be typed: `&pattern`, or `&!pattern`.
+    pub fn describe(&self) -> String {
+        let negation = if self.inverted { "!" } else { "" };
+        format!("&{}{}", negation, self.regex.as_str())
+    }
+
+    pub fn accepts(&self, line: &str) -> bool {
+        let text = ansi::visible_text(line, self.raw_control_chars);
+        self.regex.is_match(&text) != self.inverted
+    }
+
+    /// Lines found to pass so far.
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// The file line shown as line `line` of the filtered view.
+    pub fn file_line(&self, line: usize) -> Option<usize> {
+        self.lines.get(line).copied()
+    }
+
+    /// The first line of the filtered view at or after `file_line` in the
+    /// file, or the number of lines passed so far if none is.
+    pub fn line_at_or_after(&self, file_line: usize) -> usize {
+        self.lines.partition_point(|&line| line < file_line)
+    }
+
+    /// Whether every line of the file has been checked.
+    pub fn is_complete(&self, file_reader: &FileReader) -> bool {
+        file_reader.is_fully_indexed() && self.scanned >= file_reader.total_lines()
+    }
+
+    /// Checks the next block of lines.
+    pub fn step(&mut self, file_reader: &mut FileReader) -> Result<()> {
+        let lines = file_reader.get_lines(self.scanned, FILTER_BLOCK_LINES)?;
+        for (i, line) in lines.iter().enumerate() {
+            if self.accepts(line) {
+                self.lines.push(self.scanned + i);
+            }
+        }
+        self.scanned += lines.len();
+        Ok(())
+    }
+
+    /// Checks lines until `count` have passed or the file runs out.
+    pub fn scan_for(&mut self, file_reader: &mut FileReader, count: usize) -> Result<()> {
+        while self.lines.len() < count && !self.is_complete(file_reader) {
+            self.step(file_reader)?;
+        }
+        Ok(())
+    }
+
+    /// Checks lines until `file_line` has been checked or the file runs out.
+    pub fn scan_through(&mut self, file_reader: &mut FileReader, file_line: usize) -> Result<()> {
+        while self.scanned <= file_line && !self.is_complete(file_reader) {
+            self.step(file_reader)?;
+        }
+        Ok(())
+    }
+
+    pub fn scan_all(&mut self, file_reader: &mut FileReader) -> Result<()> {
+        while !self.is_complete(file_reader) {
+            self.step(file_reader)?;
+        }
+        Ok(())
+    }
+
+    /// Forgets what was found from `file_line` on, so those lines are
+    /// checked again. Used when lines may have changed, such as a last line
+    /// that was still being written.
+    pub fn rewind_to(&mut self, file_line: usize) {
+        self.lines.truncate(self.line_at_or_after(file_line));
+        self.scanned = self.scanned.min(file_line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::MorelError;
+    use crate::file_reader::{FollowMode, ReloadResult};
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    fn open(content: &str) -> (TempDir, PathBuf, FileReader) {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("app.log");
+        fs::write(&path, content).unwrap();
+        let reader = FileReader::new(&path, None, FollowMode::Name, false).unwrap();
+        (dir, path, reader)
+    }
+
+    fn passed(filter: &Filter) -> Vec<usize> {
+        (0..filter.len()).filter_map(|line| filter.file_line(line)).collect()
+    }
+
+    #[test]
+    fn only_matching_lines_pass() {
+        let (_dir, _, mut reader) = open("INFO a\nERROR b\nINFO c\nERROR d\n");
+        let mut filter = Filter::new("ERR", false, false).unwrap();
+        filter.scan_all(&mut reader).unwrap();
+        assert_eq!(passed(&filter), [1, 3]);
+        assert!(filter.is_complete(&reader));
+        assert_eq!(filter.describe(), "&ERR");
+
+        let mut filter = Filter::new("ERR", true, false).unwrap();
+        filter.scan_all(&mut reader).unwrap();
+        assert_eq!(passed(&filter), [0, 2]);
+        assert_eq!(filter.describe(), "&!ERR");
+    }
+
+    #[test]
+    fn escape_sequences_are_left_out_only_when_interpreted() {
+        let line = "\x1b[1mERR\x1b[0mOR";
+        assert!(Filter::new("ERROR", false, true).unwrap().accepts(line));
+        assert!(!Filter::new("ERROR", false, false).unwrap().accepts(line));
+    }
+
+    #[test]
+    fn an_invalid_pattern_is_an_error() {
+        assert!(matches!(
+            Filter::new("(unclosed", false, false),
+            Err(MorelError::InvalidPattern(_))
+        ));
+    }
+
+    #[test]
+    fn lines_map_to_the_first_passing_line_at_or_after_them() {
+        let (_dir, _, mut reader) = open("a\nx1\nb\nc\nx2\n");
+        let mut filter = Filter::new("x", false, false).unwrap();
+        filter.scan_for(&mut reader, 1).unwrap();
+        assert_eq!(filter.file_line(0), Some(1));
+        filter.scan_through(&mut reader, 4).unwrap();
+        assert_eq!(passed(&filter), [1, 4]);
+        assert_eq!(filter.line_at_or_after(0), 0);
+        assert_eq!(filter.line_at_or_after(2), 1);
+        assert_eq!(filter.line_at_or_after(5), 2);
+        assert_eq!(filter.file_line(2), None);
+    }
+
+    #[test]
+    fn a_completed_last_line_is_checked_again_after_an_append() {
+        let (_dir, path, mut reader) = open("INFO 0\nERROR 1\nINFO 2\nERR");
+        let mut filter = Filter::new("ERROR", false, false).unwrap();
+        filter.scan_all(&mut reader).unwrap();
+        assert_eq!(passed(&filter), [1]);
+
+        let previous_total = reader.total_lines();
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"OR 3\nINFO 4\nERROR 5\n").unwrap();
+        assert_eq!(reader.reload().unwrap(), ReloadResult::Appended);
+        filter.rewind_to(previous_total - 1);
+        assert!(!filter.is_complete(&reader));
+        filter.scan_all(&mut reader).unwrap();
+        assert_eq!(passed(&filter), [1, 3, 5]);
+    }
+
+    #[test]
+    fn edited_lines_are_checked_again() {
+        let lines = ["INFO 0", "ERROR 1", "INFO 2", "ERROR 3", "INFO 4"];
+        let (_dir, path, mut reader) = open(&format!("{}\n", lines.join("\n")));
+        let mut filter = Filter::new("ERROR", false, false).unwrap();
+        filter.scan_all(&mut reader).unwrap();
+        assert_eq!(passed(&filter), [1, 3]);
+
+        fs::write(&path, "INFO 0\nERROR 1\nERROR 2\nINFO 3\nINFO 4\nERROR 5\n").unwrap();
+        let ReloadResult::Edited { first_line } = reader.reload().unwrap() else {
+            panic!("expected an edit");
+        };
+        filter.rewind_to(first_line);
+        filter.scan_all(&mut reader).unwrap();
+        assert_eq!(passed(&filter), [1, 2, 5]);
+        assert_eq!(filter.line_at_or_after(3), 2);
+    }
+}
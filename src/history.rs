@@ -0,0 +1,170 @@
+use crate::error::Result;
+use crate::file_reader::{FileReader, ReloadResult, Rotation};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+use std::time::SystemTime;
+use tempfile::NamedTempFile;
+
+/// Largest version of the file that is kept. Larger files, such as logs,
+/// only ever grow and are not worth copying.
+const MAX_VERSION_BYTES: u64 = 16 * 1024 * 1024;
+
+/// A version of the file as it was before it last changed.
+pub struct Version {
+    /// Counts up from the first version kept, so a version can be told
+    /// apart from the others after older ones are dropped
+    id: u64,
+    file: NamedTempFile,
+    /// When the file was last modified while it had this content
+    modified: SystemTime,
+}
+
+impl Version {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    pub fn modified(&self) -> SystemTime {
+        self.modified
+    }
+}
+
+/// Past versions of a file that changes other than by growing, each copied
+/// into a temporary file before it is lost. Only the most recent versions
+/// are kept.
+pub struct History {
+    limit: usize,
+    /// Past versions, oldest first
+    versions: VecDeque<Version>,
+    /// A copy of the file's current content, kept up to date as it grows,
+    /// which becomes a past version when the file changes otherwise
+    current: Option<Version>,
+    /// Bytes copied into `current`
+    copied: u64,
+    next_id: u64,
+}
+
+impl History {
+    /// Starts keeping up to `limit` past versions of `file_reader`'s file.
+    pub fn new(file_reader: &mut FileReader, limit: usize) -> Result<Self> {
+        let mut history = Self {
+            limit,
+            versions: VecDeque::new(),
+            current: None,
+            copied: 0,
+            next_id: 1,
+        };
+        history.copy_current(file_reader)?;
+        Ok(history)
+    }
+
+    /// Keeps what the file held before `result`, if the file changed other
+    /// than by growing.
+    pub fn record(&mut self, file_reader: &mut FileReader, result: &ReloadResult) -> Result<()> {
+        match result {
+            ReloadResult::NoChange | ReloadResult::Deleted => Ok(()),
+            ReloadResult::Appended | ReloadResult::Rotated(Rotation::Detached)
+                if file_reader.byte_len() >= self.copied =>
+            {
+                self.copy_appended(file_reader)
+            }
+            _ => {
+                if self.current.as_ref().is_some_and(|current| {
+                    Self::same_content(current, file_reader).unwrap_or(false)
+                }) {
+                    return Ok(());
+                }
+                if let Some(current) = self.current.take() {
+                    self.versions.push_back(current);
+                    while self.versions.len() > self.limit {
+                        self.versions.pop_front();
+                    }
+                }
+                self.copy_current(file_reader)
+            }
+        }
+    }
+
+    /// Copies the file's content into a fresh `current`, if it is small
+    /// enough to keep.
+    fn copy_current(&mut self, file_reader: &mut FileReader) -> Result<()> {
+        self.current = None;
+        self.copied = 0;
+        if self.limit == 0 || file_reader.byte_len() > MAX_VERSION_BYTES {
+            return Ok(());
+        }
+
+        let mut file = NamedTempFile::new()?;
+        file_reader.copy_bytes(0, &mut file)?;
+        self.copied = file_reader.byte_len();
+        self.current = Some(Version {
+            id: self.next_id,
+            file,
+            modified: file_reader.modified().unwrap_or_else(SystemTime::now),
+        });
+        self.next_id += 1;
+        Ok(())
+    }
+
+    /// Adds what was appended to the file to `current`, or stops keeping
+    /// it once it grows too large.
+    fn copy_appended(&mut self, file_reader: &mut FileReader) -> Result<()> {
+        if file_reader.byte_len() > MAX_VERSION_BYTES {
+            self.current = None;
+            return Ok(());
+        }
+        let Some(current) = self.current.as_mut() else {
+            return self.copy_current(file_reader);
+        };
+
+        current.file.as_file_mut().seek(SeekFrom::Start(self.copied))?;
+        file_reader.copy_bytes(self.copied, current.file.as_file_mut())?;
+        self.copied = file_reader.byte_len();
+        if let Some(modified) = file_reader.modified() {
+            current.modified = modified;
+        }
+        Ok(())
+    }
+
+    /// Whether the file holds the same bytes as the copy of it, as after
+    /// reading it again without it having changed.
+    fn same_content(current: &Version, file_reader: &mut FileReader) -> Result<bool> {
+        if file_reader.byte_len() != current.file.as_file().metadata()?.len() {
+            return Ok(false);
+        }
+        let mut content = Vec::new();
+        file_reader.copy_bytes(0, &mut content)?;
+        Ok(fs::read(current.path())? == content)
+    }
+
+    /// How many past versions are kept.
+    pub fn len(&self) -> usize {
+        self.versions.len()
+    }
+
+    /// The past version `id`, if it is still kept, and its place among
+    /// them counting from 0 for the oldest.
+    pub fn find(&self, id: u64) -> Option<(usize, &Version)> {
+        self.versions.iter().enumerate().find(|(_, version)| version.id == id)
+    }
+
+    /// The version before `id`, or the newest one when `id` is `None` for
+    /// the file itself.
+    pub fn older(&self, id: Option<u64>) -> Option<&Version> {
+        match id {
+            None => self.versions.back(),
+            Some(id) => self.versions.iter().rev().find(|version| version.id < id),
+        }
+    }
+
+    /// The version after `id`, or `None` when the file itself comes next.
+    pub fn newer(&self, id: u64) -> Option<&Version> {
+        self.versions.iter().find(|version| version.id > id)
+    }
+}
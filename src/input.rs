@@ -1,4 +1,5 @@
 use crate::error::{MorelError, Result};
+use crate::search::SearchDirection;
 use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
 use std::time::Duration;
 
@@ -8,27 +9,77 @@ pub enum Command {
     ScrollDownLine,
     ScrollUpPage,
     ScrollUpLine,
+    /// Scroll sideways by the given number of columns, or half a screen
+    ScrollLeft(Option<usize>),
+    ScrollRight(Option<usize>),
     JumpToLine(usize),
     JumpToPercentage(u8),
     JumpToStart,
     JumpToEnd,
+    /// Jump to the next or previous region changed on the last reload
+    NextChange,
+    PreviousChange,
+    /// Show the version of the file before or after the one on screen
+    OlderVersion,
+    NewerVersion,
+    /// Compare two versions of the file
+    Diff,
+    /// Switch a diff between side by side and inline
+    ToggleSplit,
+    ToggleFollow,
+    ToggleWrap,
+    ToggleLineNumbers,
     Quit,
+    Search(String, SearchDirection),
+    /// Show only lines matching the pattern, or clear the filter if empty
+    Filter(String),
+    SearchNext,
+    SearchPrevious,
+    PromptChanged,
+    Resize(u16, u16),
     Refresh,
     Help,
     None,
 }
 
+/// What a prompt's text is for.
+#[derive(Clone, Copy)]
+enum PromptKind {
+    Search(SearchDirection),
+    Filter,
+}
+
+struct Prompt {
+    kind: PromptKind,
+    buffer: String,
+}
+
 pub struct InputHandler {
     number_buffer: String,
+    prompt: Option<Prompt>,
 }
 
 impl InputHandler {
     pub fn new() -> Self {
         Self {
             number_buffer: String::new(),
+            prompt: None,
         }
     }
 
+    /// The text of the prompt being edited, including its leading `/`, `?`
+    /// or `&`, if one is open.
+    pub fn prompt_text(&self) -> Option<String> {
+        self.prompt.as_ref().map(|prompt| {
+            let marker = match prompt.kind {
+                PromptKind::Search(SearchDirection::Forward) => '/',
+                PromptKind::Search(SearchDirection::Backward) => '?',
+                PromptKind::Filter => '&',
+            };
+            format!("{}{}", marker, prompt.buffer)
+        })
+    }
+
     pub fn read_command(&mut self, timeout: Duration) -> Result<Command> {
         if !event::poll(timeout)
             .map_err(|e| MorelError::Terminal(e.to_string()))?
@@ -41,10 +92,7 @@ impl InputHandler {
 
         match event {
             Event::Key(key_event) => Ok(self.handle_key(key_event)),
-            Event::Resize(_, _) => {
-                // Resize is handled in main loop
-                Ok(Command::None)
-            }
+            Event::Resize(width, height) => Ok(Command::Resize(width, height)),
             _ => Ok(Command::None),
         }
     }
@@ -55,6 +103,10 @@ impl InputHandler {
             return Command::Quit;
         }
 
+        if self.prompt.is_some() {
+            return self.handle_prompt_key(key);
+        }
+
         match key.code {
             // Quit commands
             KeyCode::Char('q') | KeyCode::Esc => Command::Quit,
@@ -75,6 +127,18 @@ impl InputHandler {
                 Command::ScrollUpPage
             }
 
+            // Scroll sideways
+            KeyCode::Left => {
+                let columns = self.number_buffer.parse::<usize>().ok();
+                self.number_buffer.clear();
+                Command::ScrollLeft(columns)
+            }
+            KeyCode::Right => {
+                let columns = self.number_buffer.parse::<usize>().ok();
+                self.number_buffer.clear();
+                Command::ScrollRight(columns)
+            }
+
             // Jump commands
             KeyCode::Char('g') => {
                 let cmd = if self.number_buffer.is_empty() {
@@ -114,14 +178,86 @@ impl InputHandler {
                 cmd
             }
 
+            // Changed regions
+            KeyCode::Char('c') => {
+                self.number_buffer.clear();
+                Command::NextChange
+            }
+            KeyCode::Char('C') => {
+                self.number_buffer.clear();
+                Command::PreviousChange
+            }
+
+            // Past versions
+            KeyCode::Char('[') => {
+                self.number_buffer.clear();
+                Command::OlderVersion
+            }
+            KeyCode::Char(']') => {
+                self.number_buffer.clear();
+                Command::NewerVersion
+            }
+
+            // Diffs
+            KeyCode::Char('d') => {
+                self.number_buffer.clear();
+                Command::Diff
+            }
+            KeyCode::Char('s') => {
+                self.number_buffer.clear();
+                Command::ToggleSplit
+            }
+
             // Number input for jump commands
             KeyCode::Char(c) if c.is_ascii_digit() => {
                 self.number_buffer.push(c);
                 Command::None
             }
 
+            // Follow mode
+            KeyCode::Char('F') => {
+                self.number_buffer.clear();
+                Command::ToggleFollow
+            }
+
+            // Line wrapping
+            KeyCode::Char('w') => {
+                self.number_buffer.clear();
+                Command::ToggleWrap
+            }
+
+            // Line numbers
+            KeyCode::Char('l') => {
+                self.number_buffer.clear();
+                Command::ToggleLineNumbers
+            }
+
+            // Search
+            KeyCode::Char('/') => {
+                self.number_buffer.clear();
+                self.open_prompt(PromptKind::Search(SearchDirection::Forward))
+            }
+            KeyCode::Char('?') => {
+                self.number_buffer.clear();
+                self.open_prompt(PromptKind::Search(SearchDirection::Backward))
+            }
+
+            // Filter
+            KeyCode::Char('&') => {
+                self.number_buffer.clear();
+                self.open_prompt(PromptKind::Filter)
+            }
+            KeyCode::Char('n') => {
+                self.number_buffer.clear();
+                Command::SearchNext
+            }
+            KeyCode::Char('N') => {
+                self.number_buffer.clear();
+                Command::SearchPrevious
+            }
+
             // Help
-            KeyCode::Char('h') | KeyCode::Char('?') => {
+            KeyCode::Char('h') => {
                 self.number_buffer.clear();
                 Command::Help
             }
@@ -138,6 +274,46 @@ impl InputHandler {
             }
         }
     }
+
+    fn open_prompt(&mut self, kind: PromptKind) -> Command {
+        self.prompt = Some(Prompt {
+            kind,
+            buffer: String::new(),
+        });
+        Command::PromptChanged
+    }
+
+    fn handle_prompt_key(&mut self, key: KeyEvent) -> Command {
+        let Some(prompt) = self.prompt.as_mut() else {
+            return Command::None;
+        };
+
+        match key.code {
+            KeyCode::Enter => {
+                let prompt = self.prompt.take().expect("prompt is open");
+                match prompt.kind {
+                    PromptKind::Search(direction) => Command::Search(prompt.buffer, direction),
+                    PromptKind::Filter => Command::Filter(prompt.buffer),
+                }
+            }
+            KeyCode::Esc => {
+                self.prompt = None;
+                Command::PromptChanged
+            }
+            KeyCode::Backspace => {
+                // Backspace on an empty prompt cancels it, like less
+                if prompt.buffer.pop().is_none() {
+                    self.prompt = None;
+                }
+                Command::PromptChanged
+            }
+            KeyCode::Char(c) => {
+                prompt.buffer.push(c);
+                Command::PromptChanged
+            }
+            _ => Command::None,
+        }
+    }
 }
 
 impl Default for InputHandler {
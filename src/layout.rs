@@ -0,0 +1,209 @@
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// One grapheme of a line as it appears on screen.
+#[derive(Debug, Clone)]
+pub struct Cell {
+    /// What to print: the grapheme itself, spaces for a tab, or a visible
+    /// stand-in such as `^X` for a control character
+    pub text: String,
+    /// Columns the cell takes up on screen
+    pub width: usize,
+    /// Byte range of the grapheme in the source line
+    pub source: Range<usize>,
+    /// Whether the cell stands in for something unprintable: a control
+    /// character or bytes that could not be decoded
+    pub control: bool,
+}
+
+/// Splits `line` into display cells, expanding tabs to the next multiple of
+/// `tab_width` and showing control characters in caret notation. Replacement
+/// characters from undecodable bytes are marked so they stand out.
+pub fn layout(line: &str, tab_width: usize) -> Vec<Cell> {
+    let mut cells = Vec::with_capacity(line.len());
+    let mut column = 0;
+
+    for (offset, grapheme) in line.grapheme_indices(true) {
+        let source = offset..offset + grapheme.len();
+        let cell = if grapheme == "\t" {
+            let width = tab_width - column % tab_width;
+            Cell {
+                text: " ".repeat(width),
+                width,
+                source,
+                control: false,
+            }
+        } else if let Some(text) = control_notation(grapheme) {
+            Cell {
+                width: text.len(),
+                text,
+                source,
+                control: true,
+            }
+        } else {
+            Cell {
+                text: grapheme.to_string(),
+                width: grapheme.width(),
+                source,
+                control: grapheme == "\u{FFFD}",
+            }
+        };
+        column += cell.width;
+        cells.push(cell);
+    }
+
+    cells
+}
+
+/// How a control character is shown: `^X` for C0 controls and DEL, and
+/// `<U+XXXX>` for C1 controls. `None` for anything printable.
+fn control_notation(grapheme: &str) -> Option<String> {
+    let mut chars = grapheme.chars();
+    let c = chars.next()?;
+    if chars.next().is_some() {
+        return None;
+    }
+
+    match c {
+        '\0'..='\x1f' => Some(format!("^{}", (c as u8 + b'@') as char)),
+        '\x7f' => Some("^?".to_string()),
+        '\u{80}'..='\u{9f}' => Some(format!("<U+{:04X}>", c as u32)),
+        _ => None,
+    }
+}
+
+/// Breaks `cells` into rows at most `width` columns wide, as ranges of cell
+/// indices. Every row but the last leaves its final column free for a
+/// continuation marker. A cell is never split, so a wide character that
+/// doesn't fit at the end of a row moves to the next. There is always at
+/// least one row.
+pub fn wrap_rows(cells: &[Cell], width: usize) -> Vec<Range<usize>> {
+    let mut rows = Vec::new();
+    let mut start = 0;
+    let mut remaining: usize = cells.iter().map(|cell| cell.width).sum();
+    let row_width = width.saturating_sub(1).max(1);
+
+    while remaining > width && start < cells.len() {
+        let mut end = start;
+        let mut used = 0;
+        while end < cells.len() && (end == start || used + cells[end].width <= row_width) {
+            used += cells[end].width;
+            end += 1;
+        }
+        rows.push(start..end);
+        start = end;
+        remaining -= used;
+    }
+    if start < cells.len() || rows.is_empty() {
+        rows.push(start..cells.len());
+    }
+
+    rows
+}
+
+/// Columns `text` takes up on screen.
+pub fn text_width(text: &str) -> usize {
+    text.width()
+}
+
+/// Cuts `text` to at most `width` columns.
+pub fn cut_to_width(text: &str, width: usize) -> String {
+    let mut cut = String::with_capacity(text.len().min(width));
+    let mut used = 0;
+
+    for grapheme in text.graphemes(true) {
+        let grapheme_width = grapheme.width();
+        if used + grapheme_width > width {
+            break;
+        }
+        cut.push_str(grapheme);
+        used += grapheme_width;
+    }
+
+    cut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(cells: &[Cell]) -> Vec<&str> {
+        cells.iter().map(|cell| cell.text.as_str()).collect()
+    }
+
+    fn widths(cells: &[Cell]) -> Vec<usize> {
+        cells.iter().map(|cell| cell.width).collect()
+    }
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        let cells = layout("a日本b", 8);
+        assert_eq!(texts(&cells), ["a", "日", "本", "b"]);
+        assert_eq!(widths(&cells), [1, 2, 2, 1]);
+        assert_eq!(cells[2].source, 4..7);
+    }
+
+    #[test]
+    fn grapheme_clusters_are_one_cell() {
+        // `e` with a combining acute accent, and a family joined by ZWJs
+        let family = "👨\u{200D}👩\u{200D}👧";
+        let line = format!("e\u{301}{}x", family);
+        let cells = layout(&line, 8);
+        assert_eq!(texts(&cells), ["e\u{301}", family, "x"]);
+        assert_eq!(cells[0].width, 1);
+        assert_eq!(cells[1].source, 3..3 + family.len());
+        assert_eq!(cells[1].width, text_width(family));
+        assert!(!cells.iter().any(|cell| cell.control));
+    }
+
+    #[test]
+    fn tabs_reach_the_next_tab_stop() {
+        let cells = layout("\tab\tc", 4);
+        assert_eq!(widths(&cells), [4, 1, 1, 2, 1]);
+        assert_eq!(cells[3].text, "  ");
+        assert!(!cells[0].control);
+    }
+
+    #[test]
+    fn control_characters_are_shown_in_notation() {
+        let cells = layout("a\x01\x1b\x7f\u{85}\u{FFFD}", 8);
+        assert_eq!(texts(&cells), ["a", "^A", "^[", "^?", "<U+0085>", "\u{FFFD}"]);
+        assert_eq!(widths(&cells), [1, 2, 2, 2, 8, 1]);
+        assert_eq!(
+            cells.iter().map(|cell| cell.control).collect::<Vec<_>>(),
+            [false, true, true, true, true, true]
+        );
+    }
+
+    #[test]
+    fn a_line_that_fits_is_one_row() {
+        assert_eq!(wrap_rows(&layout("abcde", 8), 5), vec![0..5]);
+        assert_eq!(wrap_rows(&[], 5), vec![0..0]);
+    }
+
+    #[test]
+    fn wrapped_rows_leave_a_column_for_the_marker() {
+        assert_eq!(wrap_rows(&layout("abcdefghij", 8), 5), [0..4, 4..8, 8..10]);
+    }
+
+    #[test]
+    fn a_wide_character_at_the_edge_moves_to_the_next_row() {
+        // Three columns are free on each row before the marker
+        let cells = layout("ab日本c", 8);
+        assert_eq!(wrap_rows(&cells, 4), [0..2, 2..3, 3..5]);
+    }
+
+    #[test]
+    fn a_cell_wider_than_a_row_gets_a_row_of_its_own() {
+        let cells = layout("a\u{85}b", 8);
+        assert_eq!(wrap_rows(&cells, 4), [0..1, 1..2, 2..3]);
+    }
+
+    #[test]
+    fn cutting_never_splits_a_character() {
+        assert_eq!(cut_to_width("日本語", 5), "日本");
+        assert_eq!(cut_to_width("e\u{301}x", 1), "e\u{301}");
+        assert_eq!(cut_to_width("abc", 0), "");
+    }
+}
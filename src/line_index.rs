@@ -0,0 +1,291 @@
+use crate::block_hash::BlockHashes;
+use crate::encoding::Encoding;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Number of lines between consecutive checkpoints. Reading a line means
+/// seeking to the checkpoint before it and skipping at most this many lines.
+pub const INDEX_STRIDE: usize = 64;
+
+const SCAN_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A sparse index of line start offsets, built incrementally.
+///
+/// Only the offset of every `INDEX_STRIDE`th line is stored, so the index of
+/// a file with a hundred million lines fits in a few megabytes. Scanning
+/// proceeds in bounded steps so a large file can be shown before it has
+/// been indexed completely.
+pub struct LineIndex {
+    /// Encoding of the data, which decides what a line break looks like
+    encoding: Encoding,
+    /// Byte offset where the text starts, after any byte order mark
+    data_start: u64,
+    /// `checkpoints[k]` is the byte offset where line `k * INDEX_STRIDE` starts
+    checkpoints: Vec<u64>,
+    /// Number of newline-terminated lines seen so far
+    complete_lines: usize,
+    /// Byte offset just after the last newline seen
+    line_start: u64,
+    /// Bytes scanned so far
+    scanned: u64,
+    /// Length of the data being indexed
+    len: u64,
+    /// UTF-16LE: offset of a `0A` byte ending the last chunk scanned, which
+    /// is a line break if the next byte is `00`
+    pending_newline: Option<u64>,
+    /// UTF-16BE: the last byte of the last chunk scanned
+    last_byte: u8,
+    /// Hashes of the bytes scanned, to tell later how much of them a
+    /// changed file still starts with
+    hashes: BlockHashes,
+}
+
+impl LineIndex {
+    /// Creates an index for `len` bytes of text in `encoding`, starting at
+    /// byte offset `data_start`.
+    pub fn new(encoding: Encoding, data_start: u64, len: u64) -> Self {
+        Self {
+            encoding,
+            data_start,
+            checkpoints: vec![data_start],
+            complete_lines: 0,
+            line_start: data_start,
+            scanned: data_start,
+            len,
+            pending_newline: None,
+            last_byte: 0,
+            hashes: BlockHashes::new(data_start),
+        }
+    }
+
+    /// Lines indexed so far. A trailing line without a newline counts as a
+    /// line, matching `BufRead::lines`.
+    pub fn total_lines(&self) -> usize {
+        self.complete_lines + usize::from(self.scanned > self.line_start)
+    }
+
+    pub fn byte_len(&self) -> u64 {
+        self.len
+    }
+
+    /// Extends the data being indexed to `len` bytes, for when the file has
+    /// only been appended to. Scanning picks up where it left off.
+    pub fn grow_to(&mut self, len: u64) {
+        self.len = self.len.max(len);
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.scanned >= self.len
+    }
+
+    /// Scans at most `budget` more bytes of `reader` for line breaks.
+    pub fn scan<R: Read + Seek>(&mut self, reader: &mut R, budget: u64) -> io::Result<()> {
+        let end = self.len.min(self.scanned.saturating_add(budget));
+        if self.scanned >= end {
+            return Ok(());
+        }
+
+        reader.seek(SeekFrom::Start(self.scanned))?;
+        let mut buffer = vec![0; SCAN_BUFFER_SIZE];
+
+        while self.scanned < end {
+            let want = ((end - self.scanned) as usize).min(buffer.len());
+            let read = reader.read(&mut buffer[..want])?;
+            if read == 0 {
+                // The data is shorter than we were told; stop at what exists
+                self.len = self.scanned;
+                break;
+            }
+
+            self.scan_chunk(&buffer[..read]);
+            self.hashes.update(&buffer[..read]);
+            self.scanned += read as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Records the line breaks in `chunk`, which starts at `self.scanned`.
+    fn scan_chunk(&mut self, chunk: &[u8]) {
+        let base = self.scanned;
+        let data_start = self.data_start;
+        let aligned = |offset: u64| (offset - data_start).is_multiple_of(2);
+
+        match self.encoding {
+            Encoding::Utf16Le => {
+                if let Some(offset) = self.pending_newline.take() {
+                    if chunk[0] == 0 {
+                        self.add_line(offset + 2);
+                    }
+                }
+                for pos in memchr::memchr_iter(b'\n', chunk) {
+                    let offset = base + pos as u64;
+                    if !aligned(offset) {
+                        continue;
+                    }
+                    match chunk.get(pos + 1) {
+                        Some(0) => self.add_line(offset + 2),
+                        Some(_) => {}
+                        None => self.pending_newline = Some(offset),
+                    }
+                }
+            }
+            Encoding::Utf16Be => {
+                for pos in memchr::memchr_iter(b'\n', chunk) {
+                    let offset = base + pos as u64;
+                    let high = if pos > 0 { chunk[pos - 1] } else { self.last_byte };
+                    if !aligned(offset) && high == 0 {
+                        self.add_line(offset + 1);
+                    }
+                }
+                self.last_byte = chunk[chunk.len() - 1];
+            }
+            Encoding::Utf8 | Encoding::Latin1 | Encoding::Windows1252 => {
+                for pos in memchr::memchr_iter(b'\n', chunk) {
+                    self.add_line(base + pos as u64 + 1);
+                }
+            }
+        }
+    }
+
+    /// Records a line ending just before byte offset `next_start`.
+    fn add_line(&mut self, next_start: u64) {
+        self.complete_lines += 1;
+        self.line_start = next_start;
+        if self.complete_lines.is_multiple_of(INDEX_STRIDE) {
+            self.checkpoints.push(next_start);
+        }
+    }
+
+    pub fn hashes(&self) -> &BlockHashes {
+        &self.hashes
+    }
+
+    /// The last checkpoint at or before byte `offset`, as a line number and
+    /// the byte offset where that line starts.
+    pub fn checkpoint_before(&self, offset: u64) -> (usize, u64) {
+        let k = self
+            .checkpoints
+            .partition_point(|&start| start <= offset)
+            .saturating_sub(1);
+        (k * INDEX_STRIDE, self.checkpoints[k])
+    }
+
+    /// The checkpoint at or before `line`, as a byte offset and the number
+    /// of lines to skip from there. `None` if `line` has not been indexed.
+    pub fn locate(&self, line: usize) -> Option<(u64, usize)> {
+        if line >= self.total_lines() {
+            return None;
+        }
+        let offset = *self.checkpoints.get(line / INDEX_STRIDE)?;
+        Some((offset, line % INDEX_STRIDE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Indexes `data` in steps of `budget` bytes, so chunks end in the
+    /// middle of lines and characters.
+    fn index(encoding: Encoding, data: &[u8], data_start: u64, budget: u64) -> LineIndex {
+        let mut index = LineIndex::new(encoding, data_start, data.len() as u64);
+        let mut reader = Cursor::new(data);
+        while !index.is_complete() {
+            index.scan(&mut reader, budget).unwrap();
+        }
+        index
+    }
+
+    fn encode(encoding: Encoding, text: &str) -> Vec<u8> {
+        match encoding {
+            Encoding::Utf16Le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            Encoding::Utf16Be => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            _ => text.as_bytes().to_vec(),
+        }
+    }
+
+    /// The bytes of `lines` joined by newlines, after `prefix`, and the
+    /// checkpoints an index of them should have.
+    fn file_of(encoding: Encoding, prefix: &[u8], lines: &[String]) -> (Vec<u8>, Vec<u64>) {
+        let mut data = prefix.to_vec();
+        let mut checkpoints = vec![prefix.len() as u64];
+        for (i, line) in lines.iter().enumerate() {
+            data.extend(encode(encoding, &format!("{}\n", line)));
+            if (i + 1) % INDEX_STRIDE == 0 {
+                checkpoints.push(data.len() as u64);
+            }
+        }
+        (data, checkpoints)
+    }
+
+    fn lines(text: &str) -> Vec<String> {
+        (0..300).map(|i| format!("{} {}", text, "x".repeat(i % 17))).collect()
+    }
+
+    #[test]
+    fn utf8_line_breaks_are_found_whatever_the_chunk_size() {
+        let lines = lines("héllo wörld");
+        let (data, checkpoints) = file_of(Encoding::Utf8, b"", &lines);
+        for budget in [1, 3, 64, 4096, u64::MAX] {
+            let index = index(Encoding::Utf8, &data, 0, budget);
+            assert_eq!(index.total_lines(), lines.len(), "budget {}", budget);
+            assert_eq!(index.checkpoints, checkpoints, "budget {}", budget);
+        }
+    }
+
+    /// `Ċ` (U+010A) and `ੁ` (U+0A41) each have a `0A` byte in their code
+    /// unit, in both byte orders, which must not be taken for a newline.
+    #[test]
+    fn utf16_line_breaks_are_whole_code_units() {
+        let lines = lines("Ċੁ text");
+        for (encoding, bom) in [
+            (Encoding::Utf16Le, &b"\xFF\xFE"[..]),
+            (Encoding::Utf16Be, &b"\xFE\xFF"[..]),
+        ] {
+            for prefix in [&b""[..], bom] {
+                let (data, checkpoints) = file_of(encoding, prefix, &lines);
+                for budget in [1, 3, 64, 4096, u64::MAX] {
+                    let index = index(encoding, &data, prefix.len() as u64, budget);
+                    let bom = !prefix.is_empty();
+                    let case = format!("{:?}, BOM {}, budget {}", encoding, bom, budget);
+                    assert_eq!(index.total_lines(), lines.len(), "{}", case);
+                    assert_eq!(index.checkpoints, checkpoints, "{}", case);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_last_line_without_a_newline_counts() {
+        let index = index(Encoding::Utf8, b"one\ntwo", 0, 2);
+        assert_eq!(index.total_lines(), 2);
+        assert_eq!(index.locate(1), Some((0, 1)));
+        assert_eq!(index.locate(2), None);
+    }
+
+    #[test]
+    fn growing_picks_up_where_scanning_stopped() {
+        let data = b"one\ntwo\nthree\n";
+        let mut index = LineIndex::new(Encoding::Utf8, 0, 8);
+        let mut reader = Cursor::new(&data[..]);
+        index.scan(&mut reader, u64::MAX).unwrap();
+        assert_eq!(index.total_lines(), 2);
+
+        index.grow_to(data.len() as u64);
+        assert!(!index.is_complete());
+        index.scan(&mut reader, u64::MAX).unwrap();
+        assert_eq!(index.total_lines(), 3);
+    }
+
+    #[test]
+    fn checkpoint_before_finds_the_line_an_offset_falls_in() {
+        let lines = lines("line");
+        let (data, checkpoints) = file_of(Encoding::Utf8, b"", &lines);
+        let index = index(Encoding::Utf8, &data, 0, u64::MAX);
+        assert_eq!(index.checkpoint_before(0), (0, 0));
+        assert_eq!(index.checkpoint_before(checkpoints[1] - 1), (0, 0));
+        assert_eq!(index.checkpoint_before(checkpoints[1]), (INDEX_STRIDE, checkpoints[1]));
+        assert_eq!(index.locate(INDEX_STRIDE + 5), Some((checkpoints[1], 5)));
+    }
+}
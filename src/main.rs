@@ -1,50 +1,214 @@
+mod anchor;
+mod ansi;
+mod block_hash;
+mod changes;
+mod cli;
+mod config;
+mod diff;
+mod diff_view;
+mod encoding;
 mod error;
 mod file_reader;
+mod filter;
+mod history;
 mod input;
+mod layout;
+mod line_index;
+mod row_cache;
+mod search;
+mod status;
+mod stdin_spool;
 mod terminal;
 mod view_state;
 mod watcher;
 
+use anchor::Anchor;
+use changes::{Changes, Shade};
+use cli::{Cli, CliAction, Options, StartPosition};
+use diff_view::{DiffSettings, DiffSide, DiffView};
+use encoding::Encoding;
 use error::{MorelError, Result};
-use file_reader::{FileReader, ReloadResult};
+use file_reader::{FileReader, FollowMode, ReloadResult, Rotation};
+use filter::Filter;
+use history::History;
 use input::{Command, InputHandler};
+use row_cache::RowCache;
+use search::{Search, SearchDirection};
+use status::{StatusInfo, StatusLine, StatusTemplate};
+use stdin_spool::StdinSpool;
 use terminal::Terminal;
-use view_state::ViewState;
+use view_state::{RowsOf, ViewState};
 use watcher::{FileEvent, FileWatcher};
 
 use std::env;
-use std::path::PathBuf;
-use std::time::Duration;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the line count shown is refreshed while a large file is being
+/// indexed in the background.
+const INDEX_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Shown when scrolling sideways while long lines are wrapped, as there is
+/// nothing off to the side to scroll to.
+const WRAPPED_MESSAGE: &str = "[Lines are wrapped - press w to chop them and scroll sideways]";
+
+/// How often the status line is redrawn to keep the time since the last
+/// change current.
+const STATUS_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a file being waited for is looked for, in case its directory
+/// couldn't be watched.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Shown for commands that act on the file itself while a past version is
+/// on screen.
+const VERSION_MESSAGE: &str = "[Showing a past version - press ] to step back to the file]";
+
+/// Where the content being paged comes from.
+enum Source {
+    File(PathBuf),
+    Stdin,
+}
 
 struct App {
+    name: String,
     file_reader: FileReader,
     view_state: ViewState,
+    row_cache: RowCache,
     terminal: Terminal,
     input_handler: InputHandler,
     watcher: FileWatcher,
+    _stdin_spool: Option<StdinSpool>,
+    search: Option<Search>,
+    filter: Option<Filter>,
+    /// The text at the top of the view when it was last drawn
+    anchor: Option<Anchor>,
+    changes: Changes,
+    /// How far faded the change marks on screen are, if any are shown
+    marks_shade: Option<Shade>,
+    history: History,
+    /// The file's own reader while a past version is shown in its place
+    live: Option<FileReader>,
+    /// The past version shown, by id, and when it was last modified
+    version: Option<(u64, SystemTime)>,
+    diff_settings: DiffSettings,
+    index_progress: Instant,
+    /// When a change to the file was last seen
+    last_change: SystemTime,
+    status_left: StatusTemplate,
+    status_right: StatusTemplate,
+    status_drawn: Instant,
     running: bool,
     status_message: Option<String>,
 }
 
 impl App {
-    fn new(path: PathBuf) -> Result<Self> {
-        let file_reader = FileReader::new(&path)?;
-        let terminal = Terminal::new()?;
+    fn new(source: Source, options: Options, terminal: Terminal) -> Result<Self> {
+        // Piped input is spooled to a temporary file as it arrives and then
+        // paged and watched like any other file
+        let (name, path, stdin_spool) = match source {
+            Source::File(path) => {
+                let name = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or("unknown")
+                    .to_string();
+                (name, path, None)
+            }
+            Source::Stdin => {
+                let spool = StdinSpool::start()?;
+                ("(stdin)".to_string(), spool.path().to_path_buf(), Some(spool))
+            }
+        };
+
+        let mut file_reader = FileReader::new(
+            &path,
+            options.encoding,
+            options.follow_mode,
+            options.generation_markers,
+        )?;
+        let changes = Changes::new(&mut file_reader, options.change_fade)?;
+        let history = History::new(&mut file_reader, options.history)?;
+        let file_modified = file_reader.modified();
         let (width, height) = Terminal::get_size()?;
         let total_lines = file_reader.total_lines();
-        let view_state = ViewState::new(width, height, total_lines);
+        let mut view_state = ViewState::new(width, height, total_lines);
+        view_state.set_line_numbers(options.line_numbers);
+        view_state.set_wrap(options.wrap);
+        view_state.set_tab_width(options.tab_width);
+        view_state.set_raw_control_chars(options.raw_control_chars);
+        view_state.set_following(options.follow);
         let input_handler = InputHandler::new();
-        let watcher = FileWatcher::new(&path)?;
+        let watcher = FileWatcher::new(&path, options.debounce, options.watcher_backend)?;
 
-        Ok(Self {
+        let mut app = Self {
+            name,
             file_reader,
             view_state,
+            row_cache: RowCache::new(options.raw_control_chars),
             terminal,
             input_handler,
             watcher,
+            _stdin_spool: stdin_spool,
+            search: None,
+            filter: None,
+            anchor: None,
+            changes,
+            marks_shade: None,
+            history,
+            live: None,
+            version: None,
+            diff_settings: DiffSettings::from_options(&options),
+            index_progress: Instant::now(),
+            last_change: file_modified.unwrap_or_else(SystemTime::now),
+            status_left: options.status_left.clone(),
+            status_right: options.status_right.clone(),
+            status_drawn: Instant::now(),
             running: true,
             status_message: None,
-        })
+        };
+        // Anything written between opening the file and starting to watch
+        // it, as is likely for a file that has only just appeared, would
+        // otherwise go unseen until it changed again
+        let known_end = known_end(&app.file_reader);
+        let result = app.file_reader.reload()?;
+        app.changes.update(&mut app.file_reader, &result, known_end)?;
+        app.history.record(&mut app.file_reader, &result)?;
+        app.sync_total_lines()?;
+
+        let encoding = app.file_reader.encoding();
+        if encoding != Encoding::Utf8 {
+            app.status_message = Some(format!("[Reading as {}]", encoding.name()));
+        }
+
+        // Following keeps the view at the end, so a start position would be
+        // overridden straight away
+        if !options.follow {
+            app.go_to_start(options.start)?;
+        }
+
+        Ok(app)
+    }
+
+    fn go_to_start(&mut self, start: StartPosition) -> Result<()> {
+        match start {
+            StartPosition::Top => Ok(()),
+            StartPosition::End => self.handle_command(Command::JumpToEnd),
+            StartPosition::Line(line) => self.handle_command(Command::JumpToLine(line)),
+            StartPosition::Pattern(pattern) => {
+                match Search::new(&pattern, SearchDirection::Forward) {
+                    Ok(search) => {
+                        self.search = Some(search);
+                        self.show_match_from(0, SearchDirection::Forward)?;
+                    }
+                    Err(e) => {
+                        self.status_message = Some(format!("[{}]", e));
+                    }
+                }
+                Ok(())
+            }
+        }
     }
 
     fn run(&mut self) -> Result<()> {
@@ -52,16 +216,37 @@ impl App {
             // Check for file changes
             self.handle_file_changes()?;
 
+            // Keep indexing and filtering large files a chunk at a time
+            // between key presses
+            let indexing = !self.file_reader.is_fully_indexed()
+                || self
+                    .filter
+                    .as_ref()
+                    .is_some_and(|filter| !filter.is_complete(&self.file_reader));
+            if indexing {
+                self.advance_index()?;
+            }
+
+            self.update_change_marks();
+
             // Check for terminal resize and render if needed
             if self.view_state.needs_redraw {
                 self.render()?;
                 self.view_state.needs_redraw = false;
+            } else if self.status_drawn.elapsed() >= STATUS_REFRESH_INTERVAL {
+                let status = self.status_line()?;
+                self.terminal.render_status(&status, self.status_message().as_deref())?;
+                self.status_drawn = Instant::now();
             }
 
-            // Read input with timeout for responsiveness
-            let command = self
-                .input_handler
-                .read_command(Duration::from_millis(50))?;
+            // Read input with timeout for responsiveness, without waiting
+            // while there is indexing or filtering left to do
+            let timeout = if indexing {
+                Duration::ZERO
+            } else {
+                Duration::from_millis(50)
+            };
+            let command = self.input_handler.read_command(timeout)?;
 
             // Handle command
             self.handle_command(command)?;
@@ -71,43 +256,334 @@ impl App {
     }
 
     fn handle_file_changes(&mut self) -> Result<()> {
-        match self.watcher.check_for_changes()? {
-            FileEvent::Modified | FileEvent::Created => {
-                match self.file_reader.reload()? {
-                    ReloadResult::NoChange => {}
-                    ReloadResult::Appended => {
-                        self.view_state
-                            .update_total_lines(self.file_reader.total_lines());
+        let event = self.watcher.check_for_changes()?;
+        // Once the file has been moved away from the watched name, changes
+        // to it can only be found by checking it directly
+        let live = self.live.as_ref().unwrap_or(&self.file_reader);
+        let check = event != FileEvent::NoChange || live.is_detached();
+        if check && self.live.is_some() {
+            return self.reload_behind_version();
+        }
+        if check {
+            let previous_total = self.file_reader.total_lines();
+            let known_end = known_end(&self.file_reader);
+            let result = self.file_reader.reload()?;
+            self.forget_changed_lines(&result, previous_total);
+            self.changes.update(&mut self.file_reader, &result, known_end)?;
+            self.history.record(&mut self.file_reader, &result)?;
+            match result {
+                ReloadResult::NoChange => {}
+                ReloadResult::Appended => {
+                    self.sync_total_lines()?;
+                    // New lines scrolling into view are signal enough
+                    // when following, so keep the follow indicator visible
+                    if !self.view_state.is_following() {
                         self.status_message = Some("[File appended]".to_string());
-                        self.view_state.needs_redraw = true;
-                    }
-                    ReloadResult::Truncated => {
-                        self.view_state
-                            .update_total_lines(self.file_reader.total_lines());
-                        self.status_message = Some("[File truncated]".to_string());
-                        self.view_state.needs_redraw = true;
                     }
-                    ReloadResult::Modified => {
-                        self.view_state
-                            .update_total_lines(self.file_reader.total_lines());
-                        self.status_message = Some("[File modified]".to_string());
-                        self.view_state.needs_redraw = true;
+                    self.view_state.needs_redraw = true;
+                }
+                ReloadResult::Truncated => {
+                    self.sync_total_lines()?;
+                    self.status_message = Some("[File truncated]".to_string());
+                    self.view_state.needs_redraw = true;
+                }
+                ReloadResult::Rewritten => {
+                    // Nothing on screen is left, so start from the top
+                    self.sync_total_lines()?;
+                    if !self.view_state.is_following() {
+                        self.show_file_line(self.file_reader.generation_start())?;
                     }
-                    ReloadResult::Deleted => {
-                        self.status_message = Some("[File deleted - showing last content]".to_string());
-                        self.view_state.needs_redraw = true;
+                    self.status_message = Some("[File rewritten]".to_string());
+                    self.view_state.needs_redraw = true;
+                }
+                ReloadResult::Edited { .. } => {
+                    self.sync_total_lines()?;
+                    self.follow_anchor(&result)?;
+                    self.status_message = Some("[File edited]".to_string());
+                    self.view_state.needs_redraw = true;
+                }
+                ReloadResult::Replaced => {
+                    // Stay with the text on screen, as when an editor reloads
+                    self.sync_total_lines()?;
+                    self.follow_anchor(&result)?;
+                    self.show_new_generation()?;
+                    self.status_message = Some("[File replaced]".to_string());
+                    self.view_state.needs_redraw = true;
+                }
+                ReloadResult::Modified => {
+                    self.sync_total_lines()?;
+                    self.follow_anchor(&result)?;
+                    self.status_message = Some("[File modified]".to_string());
+                    self.view_state.needs_redraw = true;
+                }
+                ReloadResult::Deleted => {
+                    self.status_message = Some(self.deleted_message());
+                    self.view_state.needs_redraw = true;
+                }
+                ReloadResult::Recreated => {
+                    self.sync_total_lines()?;
+                    self.show_new_generation()?;
+                    self.status_message = Some("[File recreated - showing new content]".to_string());
+                    self.view_state.needs_redraw = true;
+                }
+                ReloadResult::Rotated(rotation) => {
+                    self.sync_total_lines()?;
+                    match rotation {
+                        Rotation::Reopened => self.show_new_generation()?,
+                        Rotation::CopiedAndTruncated if !self.view_state.is_following() => {
+                            self.show_file_line(self.file_reader.generation_start())?;
+                        }
+                        _ => {}
                     }
+                    self.status_message = Some(
+                        match rotation {
+                            Rotation::Reopened => "[File rotated - showing the new file]",
+                            Rotation::CopiedAndTruncated => {
+                                "[File rotated - copied away and truncated, showing new lines]"
+                            }
+                            Rotation::Detached => "[File rotated - still following the old file]",
+                        }
+                        .to_string(),
+                    );
+                    self.view_state.needs_redraw = true;
                 }
             }
-            FileEvent::Deleted => {
-                self.status_message = Some("[File deleted - showing last content]".to_string());
-                self.view_state.needs_redraw = true;
+        }
+
+        Ok(())
+    }
+
+    /// Says the file was deleted, and whether it is still being read.
+    fn deleted_message(&self) -> String {
+        if self.file_reader.is_detached() {
+            "[File deleted - still following the old file]".to_string()
+        } else {
+            "[File deleted - waiting for it to be recreated]".to_string()
+        }
+    }
+
+    /// Drops what was worked out from lines that a reload may have changed.
+    /// When lines were only appended, just the old last line, which may
+    /// have been incomplete, is looked at again.
+    fn forget_changed_lines(&mut self, result: &ReloadResult, previous_total: usize) {
+        let first_changed = match result {
+            ReloadResult::NoChange => return,
+            ReloadResult::Deleted => {
+                self.last_change = SystemTime::now();
+                return;
+            }
+            ReloadResult::Appended | ReloadResult::Rotated(Rotation::Detached) => {
+                previous_total.saturating_sub(1)
             }
-            FileEvent::NoChange => {
-                // Don't clear status message too quickly - let it persist
+            // Lines of files that were replaced, if kept, are unchanged
+            ReloadResult::Recreated
+            | ReloadResult::Replaced
+            | ReloadResult::Rotated(Rotation::Reopened) => {
+                self.file_reader.generation_start().saturating_sub(1)
+            }
+            ReloadResult::Edited { first_line } => self.file_reader.generation_start() + first_line,
+            ReloadResult::Truncated
+            | ReloadResult::Rewritten
+            | ReloadResult::Modified
+            | ReloadResult::Rotated(Rotation::CopiedAndTruncated) => {
+                self.file_reader.generation_start()
+            }
+        };
+        self.last_change = SystemTime::now();
+        self.row_cache.clear();
+        if let Some(filter) = self.filter.as_mut() {
+            filter.rewind_to(first_changed);
+        }
+    }
+
+    /// Keeps up with changes to the file while a past version is shown in
+    /// its place, leaving the view alone.
+    fn reload_behind_version(&mut self) -> Result<()> {
+        let Some(live) = self.live.as_mut() else {
+            return Ok(());
+        };
+        let known_end = known_end(live);
+        let result = live.reload()?;
+        if result == ReloadResult::NoChange {
+            return Ok(());
+        }
+        self.changes.update(live, &result, known_end)?;
+        self.history.record(live, &result)?;
+        self.last_change = SystemTime::now();
+        self.status_message = Some("[File changed - press ] to step back to it]".to_string());
+        self.view_state.needs_redraw = true;
+        Ok(())
+    }
+
+    /// Keeps a column for change marks while they are shown, and redraws
+    /// them as they fade. Marks are for the file itself, so a past version
+    /// has none.
+    fn update_change_marks(&mut self) {
+        let shade = self.changes.shade().filter(|_| self.live.is_none());
+        if shade == self.marks_shade {
+            return;
+        }
+        self.marks_shade = shade;
+        if shade.is_some() != self.view_state.change_marks() {
+            self.view_state.set_change_marks(shade.is_some());
+            self.with_view(|view, rows_of| view.refit(rows_of));
+        }
+        self.view_state.needs_redraw = true;
+    }
+
+    /// Moves the view to wherever the text that was at its top went, when an
+    /// edit inserted or deleted lines above it.
+    fn follow_anchor(&mut self, result: &ReloadResult) -> Result<()> {
+        if self.view_state.is_following() {
+            return Ok(());
+        }
+        let Some(anchor) = &self.anchor else {
+            return Ok(());
+        };
+        if !anchor.may_have_moved(result, self.file_reader.generation_start()) {
+            return Ok(());
+        }
+        let old_line = anchor.line();
+        let Some(file_line) = anchor.find(&mut self.file_reader)? else {
+            return Ok(());
+        };
+        if file_line == old_line {
+            return Ok(());
+        }
+
+        let line = match self.filter.as_mut() {
+            Some(filter) => {
+                filter.scan_through(&mut self.file_reader, file_line)?;
+                filter.line_at_or_after(file_line)
             }
+            None => file_line,
+        };
+        let (_, visible) = self.view_state.get_visible_range();
+        self.index_through(line + visible)?;
+        self.with_view(|view, rows_of| view.shift_to(line, rows_of));
+        Ok(())
+    }
+
+    /// Brings the marker above a file that replaced the old one to the top
+    /// of the view, when old files are kept and the view isn't following.
+    fn show_new_generation(&mut self) -> Result<()> {
+        let start = self.file_reader.generation_start();
+        if start > 0 && !self.view_state.is_following() {
+            self.show_file_line(start - 1)?;
+        }
+        Ok(())
+    }
+
+    /// Indexes enough of the file to show the current view, or all of it
+    /// when following, and passes the line count on to the view.
+    fn sync_total_lines(&mut self) -> Result<()> {
+        if self.view_state.is_following() {
+            self.index_all()
+        } else {
+            let (start, count) = self.view_state.get_visible_range();
+            self.index_through(start + count)
+        }
+    }
+
+    /// Passes the number of lines indexed, or filtered, so far on to the
+    /// view.
+    fn update_total_lines(&mut self) {
+        let file_lines = self.file_reader.total_lines();
+        let total = self.filter.as_ref().map_or(file_lines, Filter::len);
+        self.with_view(|view, rows_of| view.update_total_lines(total, file_lines, rows_of));
+    }
+
+    /// The file line shown as `line` of the view, which differs from it
+    /// when a filter hides some lines.
+    fn file_line(&self, line: usize) -> usize {
+        match &self.filter {
+            Some(filter) => filter
+                .file_line(line)
+                .unwrap_or(self.file_reader.total_lines()),
+            None => line,
         }
+    }
+
+    /// Reads `count` lines of the view starting at `start`, each with the
+    /// file line it comes from.
+    fn view_lines(&mut self, start: usize, count: usize) -> Result<Vec<(usize, String)>> {
+        let Some(filter) = &self.filter else {
+            let lines = self.file_reader.get_lines(start, count)?;
+            return Ok((start..).zip(lines).collect());
+        };
+
+        let file_lines: Vec<usize> = (start..start + count)
+            .map_while(|line| filter.file_line(line))
+            .collect();
+
+        // Read each run of consecutive file lines at once
+        let mut lines = Vec::with_capacity(file_lines.len());
+        for run in file_lines.chunk_by(|a, b| a + 1 == *b) {
+            let texts = self.file_reader.get_lines(run[0], run.len())?;
+            lines.extend(run.iter().copied().zip(texts));
+        }
+        Ok(lines)
+    }
+
+    /// Runs `f` on the view, giving it a way to measure how many screen
+    /// rows a line wraps onto.
+    fn with_view<T>(&mut self, f: impl FnOnce(&mut ViewState, &mut RowsOf) -> T) -> T {
+        let Self {
+            file_reader,
+            row_cache,
+            view_state,
+            filter,
+            ..
+        } = self;
+        let tab_width = view_state.tab_width();
+        let mut rows_of = |line, width| {
+            let line = filter
+                .as_ref()
+                .and_then(|filter| filter.file_line(line))
+                .unwrap_or(line);
+            row_cache.rows(file_reader, line, width, tab_width)
+        };
+        f(view_state, &mut rows_of)
+    }
+
+    /// Indexes the file through view line `line` so the view can scroll
+    /// that far.
+    fn index_through(&mut self, line: usize) -> Result<()> {
+        match self.filter.as_mut() {
+            Some(filter) => filter.scan_for(&mut self.file_reader, line + 1)?,
+            None => self.file_reader.ensure_indexed(line)?,
+        }
+        self.update_total_lines();
+        Ok(())
+    }
 
+    fn index_all(&mut self) -> Result<()> {
+        self.file_reader.index_all()?;
+        if let Some(filter) = self.filter.as_mut() {
+            filter.scan_all(&mut self.file_reader)?;
+        }
+        self.update_total_lines();
+        Ok(())
+    }
+
+    fn advance_index(&mut self) -> Result<()> {
+        match self.filter.as_mut() {
+            Some(filter) if !filter.is_complete(&self.file_reader) => {
+                filter.step(&mut self.file_reader)?;
+            }
+            _ => {
+                self.file_reader.index_step()?;
+            }
+        }
+        let done = self.file_reader.is_fully_indexed()
+            && self
+                .filter
+                .as_ref()
+                .is_none_or(|filter| filter.is_complete(&self.file_reader));
+        if done || self.index_progress.elapsed() >= INDEX_PROGRESS_INTERVAL {
+            self.update_total_lines();
+            self.index_progress = Instant::now();
+        }
         Ok(())
     }
 
@@ -118,26 +594,50 @@ impl App {
             }
             Command::ScrollDownPage => {
                 self.status_message = None;
-                self.view_state.scroll_down_page();
+                let (start, count) = self.view_state.get_visible_range();
+                self.index_through(start + 2 * count)?;
+                self.with_view(|view, rows_of| view.scroll_down_page(rows_of));
             }
             Command::ScrollDownLine => {
                 self.status_message = None;
-                self.view_state.scroll_down_line();
+                let (start, count) = self.view_state.get_visible_range();
+                self.index_through(start + count + 1)?;
+                self.with_view(|view, rows_of| view.scroll_down_line(rows_of));
             }
             Command::ScrollUpPage => {
                 self.status_message = None;
-                self.view_state.scroll_up_page();
+                self.with_view(|view, rows_of| view.scroll_up_page(rows_of));
             }
             Command::ScrollUpLine => {
                 self.status_message = None;
-                self.view_state.scroll_up_line();
+                self.with_view(|view, rows_of| view.scroll_up_line(rows_of));
+            }
+            Command::ScrollLeft(columns) => {
+                self.status_message = None;
+                if self.view_state.wrap() {
+                    self.status_message = Some(WRAPPED_MESSAGE.to_string());
+                    self.view_state.needs_redraw = true;
+                } else {
+                    self.view_state.scroll_left(columns);
+                }
+            }
+            Command::ScrollRight(columns) => {
+                self.status_message = None;
+                if self.view_state.wrap() {
+                    self.status_message = Some(WRAPPED_MESSAGE.to_string());
+                    self.view_state.needs_redraw = true;
+                } else {
+                    let widest = self.widest_visible_line()?;
+                    self.view_state.scroll_right(columns, widest);
+                }
             }
             Command::JumpToLine(line) => {
                 self.status_message = None;
-                self.view_state.jump_to_line(line);
+                self.show_file_line(line.saturating_sub(1))?;
             }
             Command::JumpToPercentage(percent) => {
                 self.status_message = None;
+                self.index_all()?;
                 self.view_state.jump_to_percentage(percent);
             }
             Command::JumpToStart => {
@@ -146,17 +646,114 @@ impl App {
             }
             Command::JumpToEnd => {
                 self.status_message = None;
+                self.index_all()?;
                 self.view_state.jump_to_end();
             }
+            Command::NextChange | Command::PreviousChange if self.live.is_some() => {
+                self.status_message = Some(VERSION_MESSAGE.to_string());
+                self.view_state.needs_redraw = true;
+            }
+            Command::NextChange => {
+                self.status_message = None;
+                self.jump_to_change(true)?;
+                self.view_state.needs_redraw = true;
+            }
+            Command::PreviousChange => {
+                self.status_message = None;
+                self.jump_to_change(false)?;
+                self.view_state.needs_redraw = true;
+            }
+            Command::OlderVersion => {
+                self.status_message = None;
+                self.step_version(true)?;
+                self.view_state.needs_redraw = true;
+            }
+            Command::NewerVersion => {
+                self.status_message = None;
+                self.step_version(false)?;
+                self.view_state.needs_redraw = true;
+            }
+            Command::ToggleFollow => {
+                self.status_message = None;
+                let following = !self.view_state.is_following();
+                self.view_state.set_following(following);
+                self.sync_total_lines()?;
+            }
+            Command::Search(pattern, direction) => {
+                self.status_message = None;
+                self.start_search(&pattern, direction)?;
+                self.view_state.needs_redraw = true;
+            }
+            Command::Filter(pattern) => {
+                self.status_message = None;
+                self.set_filter(&pattern)?;
+                self.view_state.needs_redraw = true;
+            }
+            Command::SearchNext => {
+                self.status_message = None;
+                self.repeat_search(false)?;
+                self.view_state.needs_redraw = true;
+            }
+            Command::SearchPrevious => {
+                self.status_message = None;
+                self.repeat_search(true)?;
+                self.view_state.needs_redraw = true;
+            }
+            Command::PromptChanged => {
+                self.view_state.needs_redraw = true;
+            }
+            Command::ToggleWrap => {
+                let wrap = !self.view_state.wrap();
+                self.view_state.set_wrap(wrap);
+                self.with_view(|view, rows_of| view.refit(rows_of));
+                self.status_message = Some(if wrap {
+                    "[Wrapping long lines]".to_string()
+                } else {
+                    "[Chopping long lines]".to_string()
+                });
+            }
+            Command::ToggleLineNumbers => {
+                self.status_message = None;
+                let line_numbers = !self.view_state.line_numbers();
+                self.view_state.set_line_numbers(line_numbers);
+                self.with_view(|view, rows_of| view.refit(rows_of));
+            }
+            Command::Resize(width, height) => {
+                self.with_view(|view, rows_of| view.update_dimensions(width, height, rows_of));
+                self.sync_total_lines()?;
+            }
+            Command::Diff => {
+                self.status_message = None;
+                self.show_diff()?;
+                self.view_state.needs_redraw = true;
+            }
+            Command::Refresh if self.live.is_some() => {
+                self.status_message = Some(VERSION_MESSAGE.to_string());
+                self.view_state.needs_redraw = true;
+            }
             Command::Refresh => {
-                match self.file_reader.reload()? {
-                    ReloadResult::NoChange => {
-                        self.status_message = Some("[No changes]".to_string());
+                let previous_total = self.file_reader.total_lines();
+                let known_end = known_end(&self.file_reader);
+                let result = self.file_reader.force_reload()?;
+                self.forget_changed_lines(&result, previous_total);
+                self.changes.update(&mut self.file_reader, &result, known_end)?;
+                self.history.record(&mut self.file_reader, &result)?;
+                match result {
+                    // The file is always read again unless it is gone
+                    ReloadResult::NoChange | ReloadResult::Deleted => {
+                        self.status_message = Some(self.deleted_message());
                     }
                     _ => {
-                        self.view_state
-                            .update_total_lines(self.file_reader.total_lines());
-                        self.status_message = Some("[Refreshed]".to_string());
+                        self.sync_total_lines()?;
+                        if matches!(
+                            result,
+                            ReloadResult::Edited { .. }
+                                | ReloadResult::Replaced
+                                | ReloadResult::Modified
+                        ) {
+                            self.follow_anchor(&result)?;
+                        }
+                        self.status_message = Some("[Reloaded]".to_string());
                     }
                 }
                 self.view_state.needs_redraw = true;
@@ -164,24 +761,330 @@ impl App {
             Command::Help => {
                 self.show_help()?;
             }
-            Command::None => {}
+            Command::ToggleSplit | Command::None => {}
+        }
+
+        Ok(())
+    }
+
+    fn start_search(&mut self, pattern: &str, direction: SearchDirection) -> Result<()> {
+        // An empty pattern repeats the previous search in the new direction
+        let pattern = match (pattern.is_empty(), &self.search) {
+            (true, Some(search)) => search.pattern().to_string(),
+            (true, None) => {
+                self.status_message = Some("[No previous search pattern]".to_string());
+                return Ok(());
+            }
+            (false, _) => pattern.to_string(),
+        };
+
+        match Search::new(&pattern, direction) {
+            Ok(search) => {
+                self.search = Some(search);
+                self.jump_to_match(direction)?;
+            }
+            Err(e) => {
+                self.status_message = Some(format!("[{}]", e));
+            }
         }
 
         Ok(())
     }
 
+    fn repeat_search(&mut self, reverse: bool) -> Result<()> {
+        let Some(search) = &self.search else {
+            self.status_message = Some("[No previous search pattern]".to_string());
+            return Ok(());
+        };
+
+        let direction = if reverse {
+            search.direction().reversed()
+        } else {
+            search.direction()
+        };
+        self.jump_to_match(direction)
+    }
+
+    /// Moves the view so the next match in `direction` is the first line on
+    /// screen, starting from the current match if it is still visible or
+    /// from the top line otherwise.
+    fn jump_to_match(&mut self, direction: SearchDirection) -> Result<()> {
+        let Some(search) = &self.search else {
+            return Ok(());
+        };
+
+        let (top, visible) = self.view_state.get_visible_range();
+        let bottom = (top + visible).min(self.view_state.total_lines());
+        let (first, last) = (self.file_line(top), self.file_line(bottom.saturating_sub(1)));
+        let origin = search
+            .current_match()
+            .filter(|line| (first..=last).contains(line))
+            .unwrap_or(first);
+
+        match direction {
+            SearchDirection::Forward => self.show_match_from(origin + 1, direction),
+            SearchDirection::Backward => self.show_match_from(origin, direction),
+        }
+    }
+
+    /// Shows the first match at or after `line` when searching forward, or
+    /// the last match before it when searching backward.
+    fn show_match_from(&mut self, line: usize, direction: SearchDirection) -> Result<()> {
+        let Some(search) = &self.search else {
+            return Ok(());
+        };
+
+        // Match against the text as shown, not the escape sequences in it,
+        // and only lines the filter lets through
+        let raw_control_chars = self.view_state.raw_control_chars();
+        let filter = self.filter.as_ref();
+        let matches = |text: &str| {
+            filter.is_none_or(|filter| filter.accepts(text))
+                && search.is_match(&ansi::visible_text(text, raw_control_chars))
+        };
+        let found = match direction {
+            SearchDirection::Forward => self.file_reader.find_forward(line, matches)?,
+            SearchDirection::Backward => self.file_reader.find_backward(line, matches)?,
+        };
+
+        match found {
+            Some(line) => {
+                if let Some(search) = self.search.as_mut() {
+                    search.set_current_match(line);
+                }
+                self.show_file_line(line)?;
+            }
+            None => {
+                self.status_message =
+                    Some(format!("[Pattern not found: {}]", search.pattern()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Scrolls so `file_line` is at the top of the screen, or the first
+    /// line after it that the filter lets through.
+    fn show_file_line(&mut self, file_line: usize) -> Result<()> {
+        let line = match self.filter.as_mut() {
+            Some(filter) => {
+                filter.scan_through(&mut self.file_reader, file_line)?;
+                filter.line_at_or_after(file_line)
+            }
+            None => file_line,
+        };
+        let (_, visible) = self.view_state.get_visible_range();
+        self.index_through(line + visible)?;
+        self.view_state.jump_to_line(line + 1);
+        Ok(())
+    }
+
+    /// Scrolls the next region changed on the last reload that starts below
+    /// the top line, or the previous one above it, to the top of the screen.
+    fn jump_to_change(&mut self, forward: bool) -> Result<()> {
+        if !self.changes.has_regions() {
+            self.status_message = Some("[No lines changed on the last reload]".to_string());
+            return Ok(());
+        }
+
+        let top = self.file_line(self.view_state.top_line());
+        let found = if forward {
+            self.changes.next_after(top)
+        } else {
+            self.changes.previous_before(top)
+        };
+        match found {
+            Some(line) => self.show_file_line(line)?,
+            None => {
+                self.status_message = Some(if forward {
+                    "[No more changes below]".to_string()
+                } else {
+                    "[No more changes above]".to_string()
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Shows the past version before the one on screen, or the one after
+    /// it, which after the newest is the file itself. The line at the top
+    /// of the screen stays there.
+    fn step_version(&mut self, older: bool) -> Result<()> {
+        let shown = self.version.map(|(id, _)| id);
+        let target = match (older, shown) {
+            (true, _) => match self.history.older(shown) {
+                Some(version) => Some(version),
+                None => {
+                    self.status_message = Some("[No earlier versions kept]".to_string());
+                    return Ok(());
+                }
+            },
+            (false, Some(id)) => self.history.newer(id),
+            (false, None) => {
+                self.status_message = Some("[Already showing the file itself]".to_string());
+                return Ok(());
+            }
+        };
+        let target = target.map(|version| {
+            (version.id(), version.path().to_path_buf(), version.modified())
+        });
+
+        // Past versions have no generation marker lines, so the top line is
+        // kept as a line of the file's own content
+        let top = self
+            .file_line(self.view_state.top_line())
+            .saturating_sub(self.file_reader.generation_start());
+        match target {
+            Some((id, path, modified)) => {
+                let encoding = self.live.as_ref().unwrap_or(&self.file_reader).encoding();
+                let reader = FileReader::new(&path, Some(encoding), FollowMode::Name, false)?;
+                let shown = std::mem::replace(&mut self.file_reader, reader);
+                if self.live.is_none() {
+                    self.live = Some(shown);
+                }
+                self.version = Some((id, modified));
+                self.view_state.set_following(false);
+                self.status_message = Some("[Showing a past version, read only]".to_string());
+            }
+            None => {
+                if let Some(live) = self.live.take() {
+                    self.file_reader = live;
+                }
+                self.version = None;
+                self.status_message = Some("[Showing the file itself]".to_string());
+            }
+        }
+
+        // Nothing worked out from the lines shown before holds any more
+        self.row_cache.clear();
+        if let Some(filter) = self.filter.as_mut() {
+            filter.rewind_to(0);
+        }
+        self.anchor = None;
+        let top = top + self.file_reader.generation_start();
+        self.file_reader.ensure_indexed(top)?;
+        let last = self.file_reader.total_lines().saturating_sub(1);
+        self.show_file_line(top.min(last))
+    }
+
+    /// Compares the past version on screen with the one after it, or the
+    /// newest past version with the file itself, until the comparison is
+    /// closed.
+    fn show_diff(&mut self) -> Result<()> {
+        let shown = self.version.map(|(id, _)| id);
+        let older = match shown {
+            Some(id) => self.history.find(id).map(|(_, version)| version),
+            None => self.history.older(None),
+        };
+        let Some(older) = older else {
+            self.status_message = Some(if shown.is_some() {
+                "[This version is no longer kept]".to_string()
+            } else {
+                "[No earlier versions kept to compare with]".to_string()
+            });
+            return Ok(());
+        };
+        let older = (self.version_name(older.id()), older.path().to_path_buf());
+        let newer = shown
+            .and_then(|id| self.history.newer(id))
+            .map(|version| (self.version_name(version.id()), version.path().to_path_buf()));
+
+        let live = self.live.as_ref().unwrap_or(&self.file_reader);
+        let (encoding, live_path) = (live.encoding(), live.path().to_path_buf());
+        let settings = DiffSettings {
+            tab_width: self.view_state.tab_width(),
+            ..self.diff_settings
+        };
+        let open = || -> Result<DiffView> {
+            let left = DiffSide::open(older.0, &older.1, Some(encoding), false, &settings)?;
+            let right = match newer {
+                Some((name, path)) => DiffSide::open(name, &path, Some(encoding), false, &settings)?,
+                None => DiffSide::open(self.name.clone(), &live_path, settings.encoding, true, &settings)?,
+            };
+            let (width, height) = Terminal::get_size()?;
+            Ok(DiffView::new(left, right, &settings, width, height))
+        };
+
+        match open() {
+            Ok(mut view) => view.run(&mut self.terminal, &mut self.input_handler),
+            Err(e) => {
+                self.status_message = Some(format!("[{}]", e));
+                Ok(())
+            }
+        }
+    }
+
+    /// How a past version is named when compared, such as `app.conf
+    /// version 2/5`.
+    fn version_name(&self, id: u64) -> String {
+        let place = self.history.find(id).map_or(0, |(place, _)| place + 1);
+        format!("{} version {}/{}", self.name, place, self.history.len())
+    }
+
+    /// Shows only lines matching `pattern`, or those not matching it if it
+    /// starts with `!`. An empty pattern shows every line again. The line
+    /// at the top of the screen, or the next one shown, stays there.
+    fn set_filter(&mut self, pattern: &str) -> Result<()> {
+        let top = self.file_line(self.view_state.top_line());
+
+        if pattern.is_empty() {
+            self.filter = None;
+        } else {
+            let (pattern, inverted) = match pattern.strip_prefix('!') {
+                Some(pattern) => (pattern, true),
+                None => (pattern, false),
+            };
+            match Filter::new(pattern, inverted, self.view_state.raw_control_chars()) {
+                Ok(filter) => self.filter = Some(filter),
+                Err(e) => {
+                    self.status_message = Some(format!("[{}]", e));
+                    return Ok(());
+                }
+            }
+        }
+
+        if self.view_state.is_following() {
+            self.sync_total_lines()
+        } else {
+            self.show_file_line(top)
+        }
+    }
+
+    /// Display width of the widest line on screen.
+    fn widest_visible_line(&mut self) -> Result<usize> {
+        let (start, count) = self.view_state.get_visible_range();
+        let lines = self.view_lines(start, count)?;
+        let tab_width = self.view_state.tab_width();
+        let raw_control_chars = self.view_state.raw_control_chars();
+        Ok(lines
+            .iter()
+            .map(|(_, line)| {
+                layout::layout(&ansi::visible_text(line, raw_control_chars), tab_width)
+                    .iter()
+                    .map(|cell| cell.width)
+                    .sum()
+            })
+            .max()
+            .unwrap_or(0))
+    }
+
     fn show_help(&mut self) -> Result<()> {
         self.terminal.render_help()?;
 
-        // Wait for any key press
+        // Wait for any key press, laying the help out again on resize
         loop {
-            if let Ok(cmd) = self
+            match self
                 .input_handler
                 .read_command(Duration::from_millis(100))
             {
-                if cmd != Command::None {
-                    break;
+                Ok(Command::None) | Err(_) => {}
+                Ok(Command::Resize(width, height)) => {
+                    self.with_view(|view, rows_of| {
+                        view.update_dimensions(width, height, rows_of)
+                    });
+                    self.terminal.render_help()?;
                 }
+                Ok(_) => break,
             }
         }
 
@@ -191,23 +1094,145 @@ impl App {
 
     fn render(&mut self) -> Result<()> {
         let (start, count) = self.view_state.get_visible_range();
-        let lines = self.file_reader.get_lines(start, count);
-        let filename = self
-            .file_reader
-            .path()
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown");
+        let lines = self.view_lines(start, count)?;
+        let status = self.status_line()?;
+
+        // Remember what the reader is looking at, by the run of file lines
+        // at the top, which is all of them unless a filter hides some
+        self.anchor = lines.first().map(|&(first, _)| {
+            let run = lines
+                .iter()
+                .enumerate()
+                .take_while(|&(i, &(line, _))| line == first + i)
+                .map(|(_, (_, text))| text.as_str());
+            Anchor::new(first, run)
+        });
 
         self.terminal.render(
-            lines,
+            &lines,
             &self.view_state,
-            filename,
-            self.status_message.as_deref(),
+            self.search.as_ref(),
+            &self.changes,
+            &status,
+            self.status_message().as_deref(),
         )?;
+        self.status_drawn = Instant::now();
 
         Ok(())
     }
+
+    /// What to show in place of the left of the status line: the prompt
+    /// being typed, or else the latest message.
+    fn status_message(&self) -> Option<String> {
+        self.input_handler
+            .prompt_text()
+            .or_else(|| self.status_message.clone())
+    }
+
+    fn status_line(&mut self) -> Result<StatusLine> {
+        // Nothing is on screen when the file is empty or the filter lets
+        // no lines through
+        let (first_line, last_line, end_offset) = if self.view_state.total_lines() == 0 {
+            (0, 0, 0)
+        } else {
+            let bottom = self.with_view(|view, rows_of| view.bottom_line(rows_of));
+            let first_line = self.file_line(self.view_state.top_line());
+            let last_line = self.file_line(bottom);
+            let end_offset = self.file_reader.line_end_offset(last_line)?;
+            (first_line + 1, last_line + 1, end_offset)
+        };
+
+        let info = StatusInfo {
+            name: self.name.clone(),
+            following: self.view_state.is_following(),
+            waiting: {
+                let live = self.live.as_ref().unwrap_or(&self.file_reader);
+                live.is_deleted() && !live.is_detached()
+            },
+            first_line,
+            last_line,
+            total_lines: self.file_reader.total_lines(),
+            total_known: self.file_reader.is_fully_indexed(),
+            byte_len: self.file_reader.byte_len(),
+            end_offset,
+            modified: match self.version {
+                Some((_, modified)) => Some(modified),
+                None => self.file_reader.modified(),
+            },
+            last_change: self.last_change,
+            filter: self
+                .filter
+                .as_ref()
+                .map(|filter| (filter.describe(), filter.len())),
+            version: self.version.map(|(id, modified)| {
+                let place = self.history.find(id).map(|(place, _)| place + 1);
+                (place, self.history.len(), modified)
+            }),
+        };
+        Ok(StatusLine {
+            left: self.status_left.render(&info),
+            right: self.status_right.render(&info),
+        })
+    }
+}
+
+/// The number of lines in `file_reader`'s file, if it has been indexed to
+/// its end, so lines added by a reload can be told apart.
+fn known_end(file_reader: &FileReader) -> Option<usize> {
+    file_reader
+        .is_fully_indexed()
+        .then(|| file_reader.total_lines())
+}
+
+/// Shows a waiting screen until `path` is created, watching its directory
+/// for it to appear. Returns false if the user quit first.
+fn wait_for_file(path: &Path, terminal: &mut Terminal, options: &Options) -> Result<bool> {
+    // Without a directory to watch yet, checking once a second still works
+    let mut watcher = FileWatcher::new(path, options.debounce, options.watcher_backend).ok();
+    let mut input_handler = InputHandler::new();
+    let mut checked = Instant::now();
+    let name = path.display().to_string();
+    terminal.render_waiting(&name)?;
+
+    loop {
+        let event = match watcher.as_mut() {
+            Some(watcher) => watcher.check_for_changes()?,
+            None => FileEvent::NoChange,
+        };
+        if event != FileEvent::NoChange || checked.elapsed() >= WAIT_POLL_INTERVAL {
+            if path.exists() {
+                return Ok(true);
+            }
+            checked = Instant::now();
+        }
+
+        match input_handler.read_command(Duration::from_millis(50))? {
+            Command::Quit => return Ok(false),
+            Command::Resize(..) => terminal.render_waiting(&name)?,
+            _ => {}
+        }
+    }
+}
+
+/// Compares the files at `old` and `new` until the user quits.
+fn run_diff(old: &Path, new: &Path, options: &Options) -> Result<()> {
+    for path in [old, new] {
+        if !path.exists() {
+            return Err(MorelError::FileNotFound(path.display().to_string()));
+        }
+    }
+
+    let settings = DiffSettings::from_options(options);
+    let open = |path: &Path| {
+        let name = path.display().to_string();
+        DiffSide::open(name, path, settings.encoding, true, &settings)
+    };
+    let (left, right) = (open(old)?, open(new)?);
+
+    let mut terminal = Terminal::new()?;
+    let (width, height) = Terminal::get_size()?;
+    let mut view = DiffView::new(left, right, &settings, width, height);
+    view.run(&mut terminal, &mut InputHandler::new())
 }
 
 fn main() {
@@ -218,24 +1243,59 @@ fn main() {
 }
 
 fn run() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() < 2 {
-        eprintln!("Usage: morel <filename>");
-        eprintln!();
-        eprintln!("A live file pager that automatically updates when the file changes.");
-        eprintln!();
-        eprintln!("Press 'h' or '?' while viewing to see keyboard shortcuts.");
-        std::process::exit(1);
+    let cli = match Cli::parse(env::args().skip(1))? {
+        CliAction::Run(cli) => cli,
+        CliAction::Help => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        CliAction::Version => {
+            println!("morel {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+    };
+
+    // Settings come from the defaults, then the config file, then flags
+    let mut options = Options::default();
+    match &cli.config_path {
+        Some(path) => config::load(path, true, &mut options)?,
+        None => {
+            if let Some(path) = config::default_path() {
+                config::load(&path, false, &mut options)?;
+            }
+        }
     }
+    cli.apply_to(&mut options);
 
-    let path = PathBuf::from(&args[1]);
+    if let Some((old, new)) = &cli.diff {
+        return run_diff(old, new, &options);
+    }
+
+    let source = match cli.file.as_deref() {
+        Some("-") => Source::Stdin,
+        Some(file) => Source::File(PathBuf::from(file)),
+        // With no file given, page whatever is piped in
+        None if !io::stdin().is_terminal() => Source::Stdin,
+        None => {
+            eprintln!("{}", cli::USAGE);
+            std::process::exit(1);
+        }
+    };
 
-    if !path.exists() {
-        return Err(MorelError::FileNotFound(path.display().to_string()));
+    if let Source::File(path) = &source {
+        if !path.exists() && !options.wait {
+            return Err(MorelError::FileNotFound(path.display().to_string()));
+        }
+    }
+
+    let mut terminal = Terminal::new()?;
+    if let Source::File(path) = &source {
+        if !path.exists() && !wait_for_file(path, &mut terminal, &options)? {
+            return Ok(());
+        }
     }
 
-    let mut app = App::new(path)?;
+    let mut app = App::new(source, options, terminal)?;
     app.run()?;
 
     Ok(())
@@ -0,0 +1,69 @@
+use crate::ansi;
+use crate::file_reader::FileReader;
+use crate::layout;
+use std::collections::HashMap;
+
+/// Lines laid out together when a row count is missing from the cache.
+const BLOCK_LINES: usize = 64;
+
+/// Row counts kept before the cache starts over, to bound its memory.
+const MAX_ENTRIES: usize = 65536;
+
+/// Remembers how many screen rows each line wraps onto, so scrolling
+/// through wrapped text doesn't read and lay out the same lines again on
+/// every key press.
+pub struct RowCache {
+    /// Whether escape sequences are interpreted, and so take up no room
+    raw_control_chars: bool,
+    width: usize,
+    tab_width: usize,
+    rows: HashMap<usize, usize>,
+}
+
+impl RowCache {
+    pub fn new(raw_control_chars: bool) -> Self {
+        Self {
+            raw_control_chars,
+            width: 0,
+            tab_width: 0,
+            rows: HashMap::new(),
+        }
+    }
+
+    /// Forgets every count, for when the file's contents have changed.
+    pub fn clear(&mut self) {
+        self.rows.clear();
+    }
+
+    /// Screen rows `line` takes up when wrapped to `width` columns. Lines
+    /// that can't be read count as a single row.
+    pub fn rows(
+        &mut self,
+        file_reader: &mut FileReader,
+        line: usize,
+        width: usize,
+        tab_width: usize,
+    ) -> usize {
+        if width != self.width || tab_width != self.tab_width || self.rows.len() >= MAX_ENTRIES {
+            self.rows.clear();
+            self.width = width;
+            self.tab_width = tab_width;
+        }
+
+        if let Some(&rows) = self.rows.get(&line) {
+            return rows;
+        }
+
+        let block_start = line - line % BLOCK_LINES;
+        if let Ok(lines) = file_reader.get_lines(block_start, BLOCK_LINES) {
+            for (i, text) in lines.iter().enumerate() {
+                let text = ansi::visible_text(text, self.raw_control_chars);
+                let cells = layout::layout(&text, tab_width);
+                self.rows
+                    .insert(block_start + i, layout::wrap_rows(&cells, width).len());
+            }
+        }
+
+        *self.rows.entry(line).or_insert(1)
+    }
+}
@@ -0,0 +1,78 @@
+use crate::error::{MorelError, Result};
+use regex::Regex;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchDirection {
+    Forward,
+    Backward,
+}
+
+impl SearchDirection {
+    pub fn reversed(self) -> Self {
+        match self {
+            SearchDirection::Forward => SearchDirection::Backward,
+            SearchDirection::Backward => SearchDirection::Forward,
+        }
+    }
+}
+
+pub struct Search {
+    regex: Regex,
+    direction: SearchDirection,
+    current_match: Option<usize>,
+}
+
+/// Compiles a regex typed at a prompt, with an error short enough for the
+/// status line.
+pub fn compile(pattern: &str) -> Result<Regex> {
+    Regex::new(pattern).map_err(|e| {
+        // Syntax errors span several lines with the detail on the last
+        let message = e.to_string();
+        let detail = message.lines().last().unwrap_or_default();
+        MorelError::InvalidPattern(detail.trim_start_matches("error: ").to_string())
+    })
+}
+
+impl Search {
+    pub fn new(pattern: &str, direction: SearchDirection) -> Result<Self> {
+        Ok(Self {
+            regex: compile(pattern)?,
+            direction,
+            current_match: None,
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        self.regex.as_str()
+    }
+
+    pub fn direction(&self) -> SearchDirection {
+        self.direction
+    }
+
+    /// The file line of the match most recently jumped to. Repeated
+    /// searches continue from here so a match near the end of the file,
+    /// which cannot be scrolled to the top of the screen, is not found
+    /// twice.
+    pub fn current_match(&self) -> Option<usize> {
+        self.current_match
+    }
+
+    pub fn set_current_match(&mut self, line: usize) {
+        self.current_match = Some(line);
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        self.regex.is_match(line)
+    }
+
+    /// Byte ranges of every non-empty match in `line`, in order.
+    pub fn match_ranges(&self, line: &str) -> Vec<Range<usize>> {
+        self.regex
+            .find_iter(line)
+            .filter(|m| !m.is_empty())
+            .map(|m| m.range())
+            .collect()
+    }
+}
@@ -0,0 +1,494 @@
+use crate::ansi::Style;
+use crate::error::{MorelError, Result};
+use chrono::{DateTime, Local};
+use crossterm::style::Color;
+use std::time::{Duration, SystemTime};
+
+/// The status line shown unless the config file sets another.
+pub const DEFAULT_LEFT: &str = "--{mode}-- {file}  lines {lines}  {version}  {filter}";
+pub const DEFAULT_RIGHT: &str =
+    "{pct}% of lines  {bytepct}% of {size}  modified {mtime} ({age} ago)";
+
+/// What the status line reports about the file and the part of it on
+/// screen. Line numbers are 1-based lines of the file.
+pub struct StatusInfo {
+    pub name: String,
+    pub following: bool,
+    /// Whether the file has been deleted and is being waited for
+    pub waiting: bool,
+    /// First and last lines at least partly on screen, or 0 if there are
+    /// none
+    pub first_line: usize,
+    pub last_line: usize,
+    pub total_lines: usize,
+    /// Whether the whole file has been indexed, so `total_lines` is final
+    pub total_known: bool,
+    pub byte_len: u64,
+    /// Bytes up to the end of the last line on screen
+    pub end_offset: u64,
+    pub modified: Option<SystemTime>,
+    /// When a change to the file was last seen, or when it was opened
+    pub last_change: SystemTime,
+    /// The filter as typed, and how many lines it has let through
+    pub filter: Option<(String, usize)>,
+    /// The past version shown in place of the file: its place counting
+    /// from 1 for the oldest, unless it is no longer kept, how many are
+    /// kept, and when it was last modified
+    pub version: Option<(Option<usize>, usize, SystemTime)>,
+}
+
+/// A status line split into a part drawn from the left edge and a part
+/// drawn against the right edge.
+pub struct StatusLine {
+    pub left: Vec<StatusSpan>,
+    pub right: Vec<StatusSpan>,
+}
+
+/// Text in the status line and the style to draw it in. Colors left unset
+/// are the status line's own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusSpan {
+    pub text: String,
+    pub style: Style,
+}
+
+/// A value the status line can show.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    File,
+    Mode,
+    Line,
+    Last,
+    Lines,
+    Total,
+    Pct,
+    BytePct,
+    Size,
+    Mtime,
+    Age,
+    Filter,
+    Version,
+}
+
+/// A change to the style of the text that follows it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tag {
+    Foreground(Color),
+    Background(Color),
+    Bold,
+    Dim,
+    Italic,
+    Underline,
+    Reset,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Text(String),
+    Field(Field),
+    Tag(Tag),
+}
+
+/// One side of the status line as set in the config file, such as
+/// `{file} {line}/{total} {pct}% {mode} {mtime}`.
+///
+/// Fields in braces are filled in as the view changes, and style tags such
+/// as `{fg=red}`, `{bg=#202020}`, `{bold}` and `{reset}` change how the
+/// text after them is drawn. `{{` and `}}` stand for literal braces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusTemplate {
+    pieces: Vec<Piece>,
+}
+
+impl StatusTemplate {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or_else(|| {
+                        MorelError::Config(format!("unclosed '{{' in status template '{}'", template))
+                    })?;
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    }
+                    pieces.push(parse_placeholder(&rest[..end])?);
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => {
+                    return Err(MorelError::Config(format!(
+                        "unmatched '}}' in status template '{}'",
+                        template
+                    )));
+                }
+                _ => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+
+        Ok(Self { pieces })
+    }
+
+    /// Fills in the template from `info`.
+    pub fn render(&self, info: &StatusInfo) -> Vec<StatusSpan> {
+        let mut spans: Vec<StatusSpan> = Vec::new();
+        let mut style = Style::default();
+
+        for piece in &self.pieces {
+            let text = match piece {
+                Piece::Text(text) => text.clone(),
+                Piece::Field(field) => info.field(*field),
+                Piece::Tag(tag) => {
+                    style = tag.apply(style);
+                    continue;
+                }
+            };
+            match spans.last_mut() {
+                Some(last) if last.style == style => last.text.push_str(&text),
+                _ => spans.push(StatusSpan { text, style }),
+            }
+        }
+
+        spans
+    }
+}
+
+fn parse_placeholder(name: &str) -> Result<Piece> {
+    let field = match name {
+        "file" => Field::File,
+        "mode" => Field::Mode,
+        "line" => Field::Line,
+        "last" => Field::Last,
+        "lines" => Field::Lines,
+        "total" => Field::Total,
+        "pct" => Field::Pct,
+        "bytepct" => Field::BytePct,
+        "size" => Field::Size,
+        "mtime" => Field::Mtime,
+        "age" => Field::Age,
+        "filter" => Field::Filter,
+        "version" => Field::Version,
+        _ => return parse_tag(name).map(Piece::Tag),
+    };
+    Ok(Piece::Field(field))
+}
+
+fn parse_tag(name: &str) -> Result<Tag> {
+    let tag = match name.split_once('=') {
+        Some(("fg", color)) => Tag::Foreground(parse_color(color)?),
+        Some(("bg", color)) => Tag::Background(parse_color(color)?),
+        None if name == "bold" => Tag::Bold,
+        None if name == "dim" => Tag::Dim,
+        None if name == "italic" => Tag::Italic,
+        None if name == "underline" => Tag::Underline,
+        None if name == "reset" => Tag::Reset,
+        _ => {
+            return Err(MorelError::Config(format!(
+                "unknown status field '{{{}}}'",
+                name
+            )))
+        }
+    };
+    Ok(tag)
+}
+
+/// Parses a color name such as `red` or `dark_blue`, a palette number from
+/// 0 to 255, or `#rrggbb`.
+fn parse_color(value: &str) -> Result<Color> {
+    let invalid = || MorelError::Config(format!("invalid color '{}'", value));
+
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 {
+            return Err(invalid());
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+        return Ok(Color::Rgb {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+        });
+    }
+    if let Ok(number) = value.parse::<u8>() {
+        return Ok(Color::AnsiValue(number));
+    }
+    Color::try_from(value).map_err(|_| invalid())
+}
+
+impl Tag {
+    fn apply(self, mut style: Style) -> Style {
+        match self {
+            Tag::Foreground(color) => style.foreground = Some(color),
+            Tag::Background(color) => style.background = Some(color),
+            Tag::Bold => style.bold = true,
+            Tag::Dim => style.dim = true,
+            Tag::Italic => style.italic = true,
+            Tag::Underline => style.underline = true,
+            Tag::Reset => style = Style::default(),
+        }
+        style
+    }
+}
+
+impl StatusInfo {
+    /// How far through the file's lines the bottom of the screen is.
+    pub fn line_percent(&self) -> u64 {
+        percent(self.last_line as u64, self.total_lines as u64)
+    }
+
+    /// How far through the file's bytes the bottom of the screen is.
+    pub fn byte_percent(&self) -> u64 {
+        percent(self.end_offset, self.byte_len)
+    }
+
+    pub fn mode(&self) -> &'static str {
+        if self.waiting {
+            "Waiting"
+        } else if self.following {
+            "Follow"
+        } else {
+            "More"
+        }
+    }
+
+    /// The line range on screen and the total, such as `41-60/2000`. The
+    /// total ends in `+` while the file is still being indexed.
+    pub fn lines(&self) -> String {
+        let more = if self.total_known { "" } else { "+" };
+        if self.first_line == 0 {
+            return format!("0/{}{}", self.total_lines, more);
+        }
+        format!(
+            "{}-{}/{}{}",
+            self.first_line, self.last_line, self.total_lines, more
+        )
+    }
+
+    pub fn size(&self) -> String {
+        format_size(self.byte_len)
+    }
+
+    /// The file's modification time, as a time of day if it was today.
+    pub fn mtime(&self) -> String {
+        self.modified.map_or_else(|| "-".to_string(), format_time)
+    }
+
+    /// How long ago the last change arrived, such as `5s`.
+    pub fn age(&self) -> String {
+        let elapsed = SystemTime::now()
+            .duration_since(self.last_change)
+            .unwrap_or(Duration::ZERO);
+        format_duration(elapsed)
+    }
+
+    /// The filter as typed and how many lines it shows, or nothing when
+    /// there is no filter.
+    pub fn filter(&self) -> String {
+        match &self.filter {
+            Some((filter, count)) => format!("{} ({} shown)", filter, count),
+            None => String::new(),
+        }
+    }
+
+    /// Which past version is shown, such as `version 2/5 from 12:01:05`,
+    /// or nothing when the file itself is.
+    pub fn version(&self) -> String {
+        match self.version {
+            Some((place, count, modified)) => format!(
+                "version {}/{} from {}",
+                place.map_or("-".to_string(), |place| place.to_string()),
+                count,
+                format_time(modified)
+            ),
+            None => String::new(),
+        }
+    }
+
+    fn field(&self, field: Field) -> String {
+        match field {
+            Field::File => self.name.clone(),
+            Field::Mode => self.mode().to_string(),
+            Field::Line => self.first_line.to_string(),
+            Field::Last => self.last_line.to_string(),
+            Field::Lines => self.lines(),
+            Field::Total => self.total_lines.to_string(),
+            Field::Pct => self.line_percent().to_string(),
+            Field::BytePct => self.byte_percent().to_string(),
+            Field::Size => self.size(),
+            Field::Mtime => self.mtime(),
+            Field::Age => self.age(),
+            Field::Filter => self.filter(),
+            Field::Version => self.version(),
+        }
+    }
+}
+
+/// `part` as a percentage of `whole`, where nothing counts as all of it.
+fn percent(part: u64, whole: u64) -> u64 {
+    (part.min(whole) * 100).checked_div(whole).unwrap_or(100)
+}
+
+/// A byte count in the largest binary unit that keeps it at least 1.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// A time as a time of day if it was today, or with its date otherwise.
+fn format_time(time: SystemTime) -> String {
+    let time = DateTime::<Local>::from(time);
+    if time.date_naive() == Local::now().date_naive() {
+        time.format("%H:%M:%S").to_string()
+    } else {
+        time.format("%Y-%m-%d %H:%M").to_string()
+    }
+}
+
+/// A duration in its largest whole unit, such as `3m` or `2h`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> StatusInfo {
+        StatusInfo {
+            name: "app.log".to_string(),
+            following: false,
+            waiting: false,
+            first_line: 41,
+            last_line: 60,
+            total_lines: 200,
+            total_known: true,
+            byte_len: 3 * 1024 * 1024 / 2,
+            end_offset: 0,
+            modified: None,
+            last_change: SystemTime::now(),
+            filter: None,
+            version: None,
+        }
+    }
+
+    fn texts(template: &str) -> Vec<String> {
+        let template = StatusTemplate::parse(template).unwrap();
+        template.render(&info()).into_iter().map(|span| span.text).collect()
+    }
+
+    fn config_error(template: &str) -> String {
+        match StatusTemplate::parse(template) {
+            Err(MorelError::Config(message)) => message,
+            _ => panic!("'{}' parsed", template),
+        }
+    }
+
+    #[test]
+    fn fields_are_filled_in() {
+        assert_eq!(
+            texts("{file} {line}-{last}/{total} {lines} {pct}% {size} {mode}"),
+            ["app.log 41-60/200 41-60/200 30% 1.5 MiB More"]
+        );
+    }
+
+    #[test]
+    fn empty_fields_leave_nothing() {
+        assert_eq!(texts("[{filter}{version}]"), ["[]"]);
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(texts("{{file}} }}{{"), ["{file} }{"]);
+    }
+
+    #[test]
+    fn tags_style_the_text_after_them() {
+        let template = "a{bold}{fg=red}b{reset}c{bg=#102030}d{fg=208}e";
+        let spans = StatusTemplate::parse(template).unwrap().render(&info());
+        let bold_red = Style {
+            bold: true,
+            foreground: Some(Color::Red),
+            ..Style::default()
+        };
+        let background = Some(Color::Rgb {
+            r: 0x10,
+            g: 0x20,
+            b: 0x30,
+        });
+        assert_eq!(
+            spans,
+            [
+                StatusSpan {
+                    text: "a".to_string(),
+                    style: Style::default(),
+                },
+                StatusSpan {
+                    text: "b".to_string(),
+                    style: bold_red,
+                },
+                StatusSpan {
+                    text: "c".to_string(),
+                    style: Style::default(),
+                },
+                StatusSpan {
+                    text: "d".to_string(),
+                    style: Style {
+                        background,
+                        ..Style::default()
+                    },
+                },
+                StatusSpan {
+                    text: "e".to_string(),
+                    style: Style {
+                        background,
+                        foreground: Some(Color::AnsiValue(208)),
+                        ..Style::default()
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn the_default_templates_parse() {
+        assert!(StatusTemplate::parse(DEFAULT_LEFT).is_ok());
+        assert!(StatusTemplate::parse(DEFAULT_RIGHT).is_ok());
+    }
+
+    #[test]
+    fn bad_templates_are_config_errors() {
+        assert!(config_error("{file").contains("unclosed"));
+        assert!(config_error("file}").contains("unmatched"));
+        assert!(config_error("{nope}").contains("unknown status field"));
+        assert!(config_error("{fg=nocolor}").contains("invalid color"));
+        assert!(config_error("{bg=#12345}").contains("invalid color"));
+    }
+}
@@ -0,0 +1,34 @@
+use crate::error::Result;
+use std::io;
+use std::path::Path;
+use std::thread;
+use tempfile::NamedTempFile;
+
+/// Copies standard input into a temporary file on a background thread, so
+/// piped input can be paged, searched and followed like any other file as
+/// it arrives. The file is removed when the spool is dropped.
+///
+/// Keyboard input is unaffected: when stdin is not a terminal, crossterm
+/// reads keys from `/dev/tty` instead.
+pub struct StdinSpool {
+    file: NamedTempFile,
+}
+
+impl StdinSpool {
+    pub fn start() -> Result<Self> {
+        let file = NamedTempFile::new()?;
+        // A separate handle so the copy has its own file offset
+        let mut writer = file.reopen()?;
+
+        thread::spawn(move || {
+            // The pipe closing or the spool going away both just end the copy
+            let _ = io::copy(&mut io::stdin().lock(), &mut writer);
+        });
+
+        Ok(Self { file })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+}
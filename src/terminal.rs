@@ -1,12 +1,48 @@
+use crate::ansi::{self, Style, StyledLine};
+use crate::changes::{ChangeKind, Changes, Shade};
 use crate::error::{MorelError, Result};
+use crate::layout::{self, Cell};
+use crate::search::Search;
+use crate::status::{StatusLine, StatusSpan};
 use crate::view_state::ViewState;
 use crossterm::{
     cursor,
     execute,
-    style::{Color, ResetColor, SetBackgroundColor, SetForegroundColor},
+    style::{Attribute, Color, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor},
     terminal::{self, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use std::io::{self, Write};
+use std::ops::Range;
+
+/// Drawn in the last column of a row that a wrapped line continues past.
+const CONTINUATION_MARKER: &str = "↩";
+
+/// Drawn at the edges of a chopped line that has more text off screen.
+const MORE_LEFT_MARKER: &str = "«";
+const MORE_RIGHT_MARKER: &str = "»";
+
+/// Drawn in the gutter beside lines that changed on the last reload.
+const ADDED_MARK: &str = "+";
+const CHANGED_MARK: &str = "~";
+const DELETED_MARK: &str = "-";
+
+/// Drawn between the two sides of a side-by-side diff.
+const DIFF_SEPARATOR: &str = "│";
+
+/// How a line on one side of a diff differs from the other side.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffMark {
+    Same,
+    Removed,
+    Added,
+}
+
+/// What one side of a diff shows on a screen row: a line of its file with
+/// its line number, or nothing across from lines the other side lacks.
+pub struct DiffPane {
+    pub line: Option<(usize, String)>,
+    pub mark: DiffMark,
+}
 
 pub struct Terminal {
     _stdout: io::Stdout,
@@ -31,11 +67,16 @@ impl Terminal {
         terminal::size().map_err(|e| MorelError::Terminal(e.to_string()))
     }
 
+    /// Draws `lines`, each paired with its line number in the file, and
+    /// the status line below them. Lines in `changes` are marked in the
+    /// gutter when the view keeps a column for it.
     pub fn render(
         &mut self,
-        lines: &[String],
+        lines: &[(usize, String)],
         view: &ViewState,
-        filename: &str,
+        search: Option<&Search>,
+        changes: &Changes,
+        status: &StatusLine,
         status_message: Option<&str>,
     ) -> Result<()> {
         let mut stdout = io::stdout();
@@ -45,85 +86,392 @@ impl Terminal {
             .map_err(|e| MorelError::Terminal(e.to_string()))?;
 
         // Render visible lines
-        let term_width = view.terminal_width() as usize;
-        for (row, line) in lines.iter().enumerate() {
-            // Move cursor to the beginning of the row
-            execute!(stdout, cursor::MoveTo(0, row as u16))
-                .map_err(|e| MorelError::Terminal(e.to_string()))?;
+        let (_, height) = view.get_visible_range();
+        let gutter_width = view.gutter_width();
+        let content_width = view.content_width();
+        let shade = changes.shade().filter(|_| view.change_marks());
+        let number_width = gutter_width - usize::from(view.change_marks());
+
+        let mut row = 0;
+        for (i, (number, line)) in lines.iter().enumerate() {
+            // With escape sequences interpreted, only the text between them
+            // is laid out, and they style it
+            let styled = view.raw_control_chars().then(|| ansi::parse(line));
+            let text = styled.as_ref().map_or(line.as_str(), |s| s.text.as_str());
+            let cells = layout::layout(text, view.tab_width());
+            let matches = search.map(|s| s.match_ranges(text)).unwrap_or_default();
 
-            // Truncate or pad the line to fit terminal width
-            let display_line = if line.len() > term_width {
-                &line[..term_width]
+            // Fold the line onto several rows when wrapping, otherwise
+            // truncate it to fit terminal width
+            let rows = if view.wrap() {
+                layout::wrap_rows(&cells, content_width)
+            } else {
+                std::iter::once(0..cells.len()).collect()
+            };
+            let last_row = rows.len() - 1;
+            // The top line may be partly scrolled off the screen
+            let skip = if i == 0 { view.top_row() } else { 0 };
+            // Chopped lines may be scrolled sideways
+            let (left_column, line_width) = if view.wrap() {
+                (0, 0)
             } else {
-                line
+                (view.left_column(), cells.iter().map(|cell| cell.width).sum())
             };
 
-            write!(stdout, "{}", display_line)
-                .map_err(|e| MorelError::Terminal(e.to_string()))?;
+            for (k, cell_range) in rows.into_iter().enumerate().skip(skip) {
+                if row >= height {
+                    break;
+                }
+
+                // Move cursor to the beginning of the row
+                execute!(stdout, cursor::MoveTo(0, row as u16))
+                    .map_err(|e| MorelError::Terminal(e.to_string()))?;
+
+                if view.change_marks() {
+                    let kind = changes.kind_at(*number).filter(|_| k == 0);
+                    match (kind, shade) {
+                        (Some(kind), Some(shade)) => Self::write_change_mark(&mut stdout, kind, shade)?,
+                        _ => write!(stdout, " ").map_err(|e| MorelError::Terminal(e.to_string()))?,
+                    }
+                }
+
+                if number_width > 0 {
+                    let number = if k == 0 {
+                        (number + 1).to_string()
+                    } else {
+                        String::new()
+                    };
+                    execute!(stdout, SetAttribute(Attribute::Dim))
+                        .map_err(|e| MorelError::Terminal(e.to_string()))?;
+                    write!(stdout, "{:>width$} ", number, width = number_width - 1)
+                        .map_err(|e| MorelError::Terminal(e.to_string()))?;
+                    execute!(stdout, SetAttribute(Attribute::NormalIntensity))
+                        .map_err(|e| MorelError::Terminal(e.to_string()))?;
+                }
+
+                Self::write_cells(
+                    &mut stdout,
+                    &cells[cell_range],
+                    left_column,
+                    content_width,
+                    &matches,
+                    styled.as_ref(),
+                )?;
+
+                let right_edge = gutter_width + content_width - 1;
+                if k < last_row {
+                    Self::write_marker(&mut stdout, right_edge, row, CONTINUATION_MARKER)?;
+                }
+                if left_column > 0 && line_width > 0 {
+                    Self::write_marker(&mut stdout, gutter_width, row, MORE_LEFT_MARKER)?;
+                }
+                if line_width > left_column + content_width {
+                    Self::write_marker(&mut stdout, right_edge, row, MORE_RIGHT_MARKER)?;
+                }
+
+                row += 1;
+            }
         }
 
         // Render status line at the bottom
-        self.render_status_line(view, filename, status_message)?;
+        self.render_status(status, status_message)
+    }
 
-        stdout.flush()
+    /// Draws a diff, each screen row holding one pane for an inline diff or
+    /// two side by side, and the status line below it. Line numbers take
+    /// `number_width` columns, and text is scrolled `left_column` columns
+    /// sideways.
+    pub fn render_diff(
+        &mut self,
+        rows: &[Vec<DiffPane>],
+        number_width: usize,
+        left_column: usize,
+        tab_width: usize,
+        status: &StatusLine,
+        status_message: Option<&str>,
+    ) -> Result<()> {
+        let mut stdout = io::stdout();
+        execute!(stdout, terminal::Clear(ClearType::All))
             .map_err(|e| MorelError::Terminal(e.to_string()))?;
+        let (width, _) = Self::get_size()?;
+        let width = width as usize;
 
+        for (row, panes) in rows.iter().enumerate() {
+            let count = panes.len().max(1);
+            let pane_width = width.saturating_sub(count - 1) / count;
+            for (k, pane) in panes.iter().enumerate() {
+                let column = k * (pane_width + 1);
+                if k > 0 {
+                    Self::write_marker(&mut stdout, column - 1, row, DIFF_SEPARATOR)?;
+                }
+                let Some((number, text)) = &pane.line else {
+                    continue;
+                };
+
+                let (sign, color) = match pane.mark {
+                    DiffMark::Same => (" ", None),
+                    DiffMark::Removed => ("-", Some(Color::Red)),
+                    DiffMark::Added => ("+", Some(Color::Green)),
+                };
+                execute!(
+                    stdout,
+                    cursor::MoveTo(column as u16, row as u16),
+                    SetAttribute(Attribute::Dim)
+                )
+                .map_err(|e| MorelError::Terminal(e.to_string()))?;
+                write!(stdout, "{:>width$} ", number + 1, width = number_width)
+                    .map_err(|e| MorelError::Terminal(e.to_string()))?;
+                execute!(stdout, SetAttribute(Attribute::NormalIntensity))
+                    .map_err(|e| MorelError::Terminal(e.to_string()))?;
+                if let Some(color) = color {
+                    execute!(stdout, SetForegroundColor(color))
+                        .map_err(|e| MorelError::Terminal(e.to_string()))?;
+                }
+                write!(stdout, "{}", sign)
+                    .map_err(|e| MorelError::Terminal(e.to_string()))?;
+
+                let cells = layout::layout(text, tab_width);
+                let text_width = pane_width.saturating_sub(number_width + 2);
+                Self::write_cells(&mut stdout, &cells, left_column, text_width, &[], None)?;
+                execute!(stdout, SetAttribute(Attribute::Reset))
+                    .map_err(|e| MorelError::Terminal(e.to_string()))?;
+            }
+        }
+
+        self.render_status(status, status_message)
+    }
+
+    /// Writes the mark for a line that changed in the way `kind` says, as
+    /// far faded as `shade`.
+    fn write_change_mark(stdout: &mut io::Stdout, kind: ChangeKind, shade: Shade) -> Result<()> {
+        let (mark, color) = match kind {
+            ChangeKind::Added => (ADDED_MARK, Color::Green),
+            ChangeKind::Changed => (CHANGED_MARK, Color::Yellow),
+            ChangeKind::Deleted => (DELETED_MARK, Color::Red),
+        };
+        let attribute = match shade {
+            Shade::Bright => Attribute::Bold,
+            Shade::Normal => Attribute::NormalIntensity,
+            Shade::Dim => Attribute::Dim,
+        };
+        execute!(stdout, SetForegroundColor(color), SetAttribute(attribute))
+            .map_err(|e| MorelError::Terminal(e.to_string()))?;
+        write!(stdout, "{}", mark)
+            .map_err(|e| MorelError::Terminal(e.to_string()))?;
+        execute!(stdout, SetAttribute(Attribute::Reset))
+            .map_err(|e| MorelError::Terminal(e.to_string()))?;
         Ok(())
     }
 
-    fn render_status_line(
-        &mut self,
-        view: &ViewState,
-        filename: &str,
-        status_message: Option<&str>,
+    /// Draws a dim marker over the screen cell at `column`.
+    fn write_marker(stdout: &mut io::Stdout, column: usize, row: usize, marker: &str) -> Result<()> {
+        execute!(
+            stdout,
+            cursor::MoveTo(column as u16, row as u16),
+            SetAttribute(Attribute::Dim)
+        )
+        .map_err(|e| MorelError::Terminal(e.to_string()))?;
+        write!(stdout, "{}", marker)
+            .map_err(|e| MorelError::Terminal(e.to_string()))?;
+        execute!(stdout, SetAttribute(Attribute::NormalIntensity))
+            .map_err(|e| MorelError::Terminal(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Writes as many of `cells` as fit in `width` columns after skipping
+    /// the first `skip` columns, in the styles from `styled` if given.
+    /// Search matches and control characters are shown in reverse video.
+    fn write_cells(
+        stdout: &mut io::Stdout,
+        cells: &[Cell],
+        skip: usize,
+        width: usize,
+        matches: &[Range<usize>],
+        styled: Option<&StyledLine>,
     ) -> Result<()> {
+        let mut column = 0;
+        let mut used = 0;
+        let mut current = Style::default();
+
+        for cell in cells {
+            let cell_start = column;
+            column += cell.width;
+            if column <= skip {
+                continue;
+            }
+            if cell_start < skip {
+                // A wide character or tab cut by the left edge leaves blank
+                // columns, as many as fit
+                let visible = (column - skip).min(width - used);
+                write!(stdout, "{}", " ".repeat(visible))
+                    .map_err(|e| MorelError::Terminal(e.to_string()))?;
+                used += visible;
+                if used == width {
+                    break;
+                }
+                continue;
+            }
+
+            if used + cell.width > width {
+                // A wide character cut by the edge leaves blank columns
+                write!(stdout, "{}", " ".repeat(width.saturating_sub(used)))
+                    .map_err(|e| MorelError::Terminal(e.to_string()))?;
+                break;
+            }
+
+            let is_match = matches
+                .iter()
+                .any(|m| m.start < cell.source.end && cell.source.start < m.end);
+            let mut style = styled
+                .map(|s| s.style_at(cell.source.start))
+                .unwrap_or_default();
+            if is_match || cell.control {
+                style.reverse = !style.reverse;
+            }
+            if style != current {
+                Self::set_style(stdout, &style)?;
+                current = style;
+            }
+
+            write!(stdout, "{}", cell.text)
+                .map_err(|e| MorelError::Terminal(e.to_string()))?;
+            used += cell.width;
+        }
+
+        // Styles never carry over to the next row
+        if current != Style::default() {
+            execute!(stdout, SetAttribute(Attribute::Reset))
+                .map_err(|e| MorelError::Terminal(e.to_string()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Switches the terminal to `style`, starting from plain text.
+    fn set_style(stdout: &mut io::Stdout, style: &Style) -> Result<()> {
+        execute!(stdout, SetAttribute(Attribute::Reset))
+            .map_err(|e| MorelError::Terminal(e.to_string()))?;
+
+        let attributes = [
+            (style.bold, Attribute::Bold),
+            (style.dim, Attribute::Dim),
+            (style.italic, Attribute::Italic),
+            (style.underline, Attribute::Underlined),
+            (style.reverse, Attribute::Reverse),
+            (style.crossed_out, Attribute::CrossedOut),
+        ];
+        for (_, attribute) in attributes.iter().filter(|(on, _)| *on) {
+            execute!(stdout, SetAttribute(*attribute))
+                .map_err(|e| MorelError::Terminal(e.to_string()))?;
+        }
+        if let Some(color) = style.foreground {
+            execute!(stdout, SetForegroundColor(color))
+                .map_err(|e| MorelError::Terminal(e.to_string()))?;
+        }
+        if let Some(color) = style.background {
+            execute!(stdout, SetBackgroundColor(color))
+                .map_err(|e| MorelError::Terminal(e.to_string()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Draws the status line: `status`, with `message` in place of its
+    /// left part if given. The right part is dropped when there is no room
+    /// for both.
+    pub fn render_status(&mut self, status: &StatusLine, message: Option<&str>) -> Result<()> {
         let mut stdout = io::stdout();
         let (width, height) = Self::get_size()?;
+        let width = width as usize;
         let status_row = height - 1;
 
         // Move to status line
         execute!(stdout, cursor::MoveTo(0, status_row))
             .map_err(|e| MorelError::Terminal(e.to_string()))?;
 
-        // Set status line colors (inverted)
-        execute!(
-            stdout,
-            SetBackgroundColor(Color::White),
-            SetForegroundColor(Color::Black)
-        )
-        .map_err(|e| MorelError::Terminal(e.to_string()))?;
-
-        // Build status message
-        let status = if let Some(msg) = status_message {
-            format!("{:<width$}", msg, width = width as usize)
-        } else {
-            let percentage = view.get_percentage();
-            let current_line = view.top_line() + 1;
-            let total_lines = view.get_visible_range().0 + view.get_visible_range().1;
-
-            let status_text = format!(
-                "--More-- ({}%) line {}/{}  {}",
-                percentage,
-                current_line,
-                total_lines.max(current_line),
-                filename
-            );
-
-            // Pad to full width
-            format!("{:<width$}", status_text, width = width as usize)
+        let message_span;
+        let left = match message {
+            Some(message) => {
+                message_span = [StatusSpan {
+                    text: message.to_string(),
+                    style: Style::default(),
+                }];
+                &message_span[..]
+            }
+            None => &status.left[..],
         };
+        let spans_width =
+            |spans: &[StatusSpan]| spans.iter().map(|span| layout::text_width(&span.text)).sum::<usize>();
+        let right_width = spans_width(&status.right);
+        let show_right = spans_width(left) + 2 + right_width <= width;
 
-        // Print status line
-        write!(stdout, "{}", status)
-            .map_err(|e| MorelError::Terminal(e.to_string()))?;
+        let left_room = if show_right { width - right_width } else { width };
+        let used = Self::write_status_spans(&mut stdout, left, left_room)?;
+        // Pad out the rest of the left part in the status line's colors
+        Self::write_status_spans(
+            &mut stdout,
+            &[StatusSpan {
+                text: " ".repeat(left_room - used),
+                style: Style::default(),
+            }],
+            left_room - used,
+        )?;
+        if show_right {
+            Self::write_status_spans(&mut stdout, &status.right, right_width)?;
+        }
 
         // Reset colors
-        execute!(stdout, ResetColor)
+        execute!(stdout, SetAttribute(Attribute::Reset), ResetColor)
+            .map_err(|e| MorelError::Terminal(e.to_string()))?;
+
+        stdout.flush()
             .map_err(|e| MorelError::Terminal(e.to_string()))?;
 
         Ok(())
     }
 
+    /// Writes as much of `spans` as fits in `width` columns, returning the
+    /// columns used. Colors a span leaves unset are the status line's own
+    /// black on white.
+    fn write_status_spans(stdout: &mut io::Stdout, spans: &[StatusSpan], width: usize) -> Result<usize> {
+        let mut used = 0;
+        for span in spans {
+            let text = layout::cut_to_width(&span.text, width - used);
+            let style = Style {
+                foreground: span.style.foreground.or(Some(Color::Black)),
+                background: span.style.background.or(Some(Color::White)),
+                ..span.style
+            };
+            Self::set_style(stdout, &style)?;
+            write!(stdout, "{}", text)
+                .map_err(|e| MorelError::Terminal(e.to_string()))?;
+            used += layout::text_width(&text);
+        }
+        Ok(used)
+    }
+
+    /// Draws the screen shown while waiting for `path` to be created.
+    pub fn render_waiting(&mut self, path: &str) -> Result<()> {
+        let mut stdout = io::stdout();
+        let (width, height) = Self::get_size()?;
+
+        execute!(stdout, terminal::Clear(ClearType::All))
+            .map_err(|e| MorelError::Terminal(e.to_string()))?;
+
+        let text = layout::cut_to_width(&format!("Waiting for {} to appear...", path), width as usize);
+        let column = (width as usize).saturating_sub(layout::text_width(&text)) / 2;
+        execute!(stdout, cursor::MoveTo(column as u16, height.saturating_sub(1) / 2))
+            .map_err(|e| MorelError::Terminal(e.to_string()))?;
+        write!(stdout, "{}", text)
+            .map_err(|e| MorelError::Terminal(e.to_string()))?;
+
+        let status = StatusLine {
+            left: Vec::new(),
+            right: Vec::new(),
+        };
+        self.render_status(&status, Some("--Waiting-- press q to quit"))
+    }
+
     pub fn render_help(&mut self) -> Result<()> {
         let mut stdout = io::stdout();
         let (_width, height) = Self::get_size()?;
@@ -143,15 +491,37 @@ impl Terminal {
             "    b / Up        Scroll up one page/line",
             "    k             Scroll up one line",
             "",
+            "  Searching:",
+            "    /pattern      Search forward for a regex",
+            "    ?pattern      Search backward for a regex",
+            "    n             Repeat search in the same direction",
+            "    N             Repeat search in the opposite direction",
+            "    &pattern      Show only lines matching a regex",
+            "    &!pattern     Show only lines not matching a regex",
+            "    &             Show all lines again",
+            "",
+            "  Scrolling sideways (when long lines are chopped):",
+            "    Left / Right  Scroll half a screen, or [n] columns",
+            "",
             "  Jumping:",
             "    g             Jump to start of file",
             "    G             Jump to end of file",
             "    [n]G          Jump to line n",
             "    [n]%          Jump to n% through file",
+            "    c / C         Jump to the next / previous changed region",
+            "",
+            "  History:",
+            "    [             Show the version before the one on screen",
+            "    ]             Show the version after, back to the file itself",
+            "    d             Compare the version on screen with the one after it",
+            "    s             Switch a comparison between side by side and inline",
             "",
             "  Other:",
-            "    r             Force refresh",
-            "    h / ?         Show this help",
+            "    F             Toggle follow mode",
+            "    w             Toggle wrapping of long lines",
+            "    l             Toggle line numbers",
+            "    r             Reload the file from disk",
+            "    h             Show this help",
             "    q / Esc       Quit",
             "",
             "  Live Update:",
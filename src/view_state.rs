@@ -1,8 +1,31 @@
+/// Counts the screen rows a line takes up when wrapped to a given width,
+/// called as `rows_of(line, width)`.
+pub type RowsOf<'a> = dyn FnMut(usize, usize) -> usize + 'a;
+
+/// What is on screen. The top of the view is a position within a line,
+/// since a wrapped line may be partly scrolled off the top. Anything that
+/// depends on how lines wrap takes a `RowsOf` to measure them with.
 pub struct ViewState {
     top_line: usize,
+    /// Rows of the top line scrolled off the top of the screen
+    top_row: usize,
+    /// The top position that shows the last row of the file at the bottom
+    end: (usize, usize),
+    /// Columns scrolled off the left edge when long lines are chopped
+    left_column: usize,
     terminal_height: u16,
     terminal_width: u16,
     total_lines: usize,
+    /// Lines in the whole file, which sizes the line number gutter even
+    /// when a filter hides some of them
+    file_lines: usize,
+    following: bool,
+    line_numbers: bool,
+    /// Whether a column is kept for marking lines that just changed
+    change_marks: bool,
+    wrap: bool,
+    tab_width: usize,
+    raw_control_chars: bool,
     pub needs_redraw: bool,
 }
 
@@ -10,109 +33,414 @@ impl ViewState {
     pub fn new(terminal_width: u16, terminal_height: u16, total_lines: usize) -> Self {
         Self {
             top_line: 0,
+            top_row: 0,
+            end: (total_lines.saturating_sub(terminal_height.saturating_sub(1) as usize), 0),
+            left_column: 0,
             terminal_height: terminal_height.saturating_sub(1), // Reserve 1 line for status
             terminal_width,
             total_lines,
+            file_lines: total_lines,
+            following: false,
+            line_numbers: false,
+            change_marks: false,
+            wrap: true,
+            tab_width: 8,
+            raw_control_chars: false,
             needs_redraw: true,
         }
     }
 
-    pub fn scroll_down_page(&mut self) {
-        let page_size = self.terminal_height as usize;
-        self.top_line = (self.top_line + page_size).min(self.max_top_line());
+    /// Shows or hides the line number gutter. Call `refit` afterwards, as
+    /// the gutter takes room from the text.
+    pub fn set_line_numbers(&mut self, line_numbers: bool) {
+        self.line_numbers = line_numbers;
         self.needs_redraw = true;
     }
 
-    pub fn scroll_down_line(&mut self) {
-        if self.top_line < self.max_top_line() {
-            self.top_line += 1;
-            self.needs_redraw = true;
+    pub fn line_numbers(&self) -> bool {
+        self.line_numbers
+    }
+
+    /// Shows or hides the column marking changed lines. Call `refit`
+    /// afterwards, as the column takes room from the text.
+    pub fn set_change_marks(&mut self, change_marks: bool) {
+        self.change_marks = change_marks;
+        self.needs_redraw = true;
+    }
+
+    pub fn change_marks(&self) -> bool {
+        self.change_marks
+    }
+
+    /// Turns wrapping on or off. Call `refit` afterwards, as the end of
+    /// the file moves.
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
+        if wrap {
+            self.left_column = 0;
+        } else {
+            self.top_row = 0;
         }
+        self.needs_redraw = true;
+    }
+
+    pub fn wrap(&self) -> bool {
+        self.wrap
     }
 
-    pub fn scroll_up_page(&mut self) {
-        let page_size = self.terminal_height as usize;
-        self.top_line = self.top_line.saturating_sub(page_size);
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        self.tab_width = tab_width.max(1);
         self.needs_redraw = true;
     }
 
-    pub fn scroll_up_line(&mut self) {
-        if self.top_line > 0 {
-            self.top_line -= 1;
+    pub fn tab_width(&self) -> usize {
+        self.tab_width
+    }
+
+    /// Sets whether ANSI escape sequences are interpreted rather than shown
+    /// as control characters.
+    pub fn set_raw_control_chars(&mut self, raw_control_chars: bool) {
+        self.raw_control_chars = raw_control_chars;
+        self.needs_redraw = true;
+    }
+
+    pub fn raw_control_chars(&self) -> bool {
+        self.raw_control_chars
+    }
+
+    /// Columns left for text once the gutter is drawn.
+    pub fn content_width(&self) -> usize {
+        (self.terminal_width as usize)
+            .saturating_sub(self.gutter_width())
+            .max(1)
+    }
+
+    /// Columns taken up by the change marks, line numbers and the space
+    /// after them.
+    pub fn gutter_width(&self) -> usize {
+        let numbers = if self.line_numbers {
+            self.file_lines.max(1).to_string().len() + 1
+        } else {
+            0
+        };
+        numbers + usize::from(self.change_marks)
+    }
+
+    pub fn scroll_down_page(&mut self, rows_of: &mut RowsOf) {
+        self.following = false;
+        self.scroll_down_rows(self.terminal_height as usize, rows_of);
+    }
+
+    pub fn scroll_down_line(&mut self, rows_of: &mut RowsOf) {
+        self.following = false;
+        self.scroll_down_rows(1, rows_of);
+    }
+
+    pub fn scroll_up_page(&mut self, rows_of: &mut RowsOf) {
+        self.following = false;
+        self.scroll_up_rows(self.terminal_height as usize, rows_of);
+    }
+
+    pub fn scroll_up_line(&mut self, rows_of: &mut RowsOf) {
+        self.following = false;
+        self.scroll_up_rows(1, rows_of);
+    }
+
+    fn scroll_down_rows(&mut self, count: usize, rows_of: &mut RowsOf) {
+        for _ in 0..count {
+            if self.position() >= self.end {
+                break;
+            }
+            if self.top_row + 1 < self.rows(self.top_line, rows_of) {
+                self.top_row += 1;
+            } else {
+                self.top_line += 1;
+                self.top_row = 0;
+            }
             self.needs_redraw = true;
         }
     }
 
-    pub fn jump_to_line(&mut self, line: usize) {
-        self.top_line = line.saturating_sub(1).min(self.max_top_line());
+    fn scroll_up_rows(&mut self, count: usize, rows_of: &mut RowsOf) {
+        for _ in 0..count {
+            if self.top_row > 0 {
+                self.top_row -= 1;
+            } else if self.top_line > 0 {
+                self.top_line -= 1;
+                self.top_row = self.rows(self.top_line, rows_of) - 1;
+            } else {
+                break;
+            }
+            self.needs_redraw = true;
+        }
+    }
+
+    /// Scrolls `columns` to the left, or half a screen by default.
+    pub fn scroll_left(&mut self, columns: Option<usize>) {
+        let columns = columns.unwrap_or(self.content_width() / 2);
+        self.left_column = self.left_column.saturating_sub(columns);
+        self.needs_redraw = true;
+    }
+
+    /// Scrolls `columns` to the right, or half a screen by default, stopping
+    /// once the end of the widest line on screen, `widest` columns wide, is
+    /// in view.
+    pub fn scroll_right(&mut self, columns: Option<usize>, widest: usize) {
+        let columns = columns.unwrap_or(self.content_width() / 2);
+        let limit = widest.saturating_sub(self.content_width()).max(self.left_column);
+        self.left_column = (self.left_column + columns).min(limit);
         self.needs_redraw = true;
     }
 
+    pub fn jump_to_line(&mut self, line: usize) {
+        self.following = false;
+        self.move_to(line.saturating_sub(1));
+    }
+
+    /// Puts the line `percent` of the way through at the top. This counts
+    /// lines rather than wrapped rows, to agree with the percentage the
+    /// status line shows, and because counting rows would mean laying out
+    /// every line of the file on each jump.
     pub fn jump_to_percentage(&mut self, percent: u8) {
+        self.following = false;
         let percent = percent.min(100) as usize;
-        let target_line = (self.total_lines * percent) / 100;
-        self.top_line = target_line.min(self.max_top_line());
-        self.needs_redraw = true;
+        self.move_to((self.total_lines * percent) / 100);
     }
 
     pub fn jump_to_start(&mut self) {
-        self.top_line = 0;
-        self.needs_redraw = true;
+        self.following = false;
+        self.move_to(0);
     }
 
     pub fn jump_to_end(&mut self) {
-        self.top_line = self.max_top_line();
+        (self.top_line, self.top_row) = self.end;
         self.needs_redraw = true;
     }
 
-    pub fn update_total_lines(&mut self, total: usize) {
-        self.total_lines = total;
-        // Adjust top_line if it's now out of bounds
-        if self.top_line > self.max_top_line() {
-            self.top_line = self.max_top_line();
+    /// Moves the line at the top of the screen to `line`, keeping as many
+    /// of its rows scrolled past, for when the text there moved after lines
+    /// were inserted or deleted above it.
+    pub fn shift_to(&mut self, line: usize, rows_of: &mut RowsOf) {
+        self.top_line = line;
+        self.refit(rows_of);
+    }
+
+    /// Puts `line` at the top of the screen, or as near as it can go while
+    /// still filling the screen.
+    fn move_to(&mut self, line: usize) {
+        (self.top_line, self.top_row) = (line, 0).min(self.end);
+        self.needs_redraw = true;
+    }
+
+    /// Starts or stops follow mode. While following, the view stays pinned
+    /// to the end of the file as new lines arrive.
+    pub fn set_following(&mut self, following: bool) {
+        self.following = following;
+        if following {
+            (self.top_line, self.top_row) = self.end;
         }
         self.needs_redraw = true;
     }
 
-    pub fn update_dimensions(&mut self, width: u16, height: u16) {
+    pub fn is_following(&self) -> bool {
+        self.following
+    }
+
+    /// Sets the number of lines there are to view, out of `file_lines` in
+    /// the whole file.
+    pub fn update_total_lines(&mut self, total: usize, file_lines: usize, rows_of: &mut RowsOf) {
+        self.total_lines = total;
+        self.file_lines = file_lines;
+        self.refit(rows_of);
+    }
+
+    pub fn update_dimensions(&mut self, width: u16, height: u16, rows_of: &mut RowsOf) {
         self.terminal_width = width;
         self.terminal_height = height.saturating_sub(1); // Reserve 1 line for status
-        // Adjust top_line if necessary
-        if self.top_line > self.max_top_line() {
-            self.top_line = self.max_top_line();
+        self.refit(rows_of);
+    }
+
+    /// Measures the end of the file again after anything that changes how
+    /// lines wrap, keeping the view within bounds.
+    pub fn refit(&mut self, rows_of: &mut RowsOf) {
+        self.end = self.end_position(rows_of);
+        self.top_row = self
+            .top_row
+            .min(self.rows(self.top_line, rows_of).saturating_sub(1));
+        // Adjust the top if it's now out of bounds
+        if self.following || self.position() > self.end {
+            (self.top_line, self.top_row) = self.end;
         }
         self.needs_redraw = true;
     }
 
+    /// Works back from the last line until the screen is full.
+    fn end_position(&self, rows_of: &mut RowsOf) -> (usize, usize) {
+        let mut remaining = self.terminal_height as usize;
+        if remaining == 0 {
+            return (self.total_lines, 0);
+        }
+
+        let mut line = self.total_lines;
+        while line > 0 {
+            line -= 1;
+            let rows = self.rows(line, rows_of);
+            if rows >= remaining {
+                return (line, rows - remaining);
+            }
+            remaining -= rows;
+        }
+        (0, 0)
+    }
+
+    fn rows(&self, line: usize, rows_of: &mut RowsOf) -> usize {
+        if self.wrap {
+            rows_of(line, self.content_width()).max(1)
+        } else {
+            1
+        }
+    }
+
+    fn position(&self) -> (usize, usize) {
+        (self.top_line, self.top_row)
+    }
+
+    /// The first line on screen and the most lines that can fit below it.
     pub fn get_visible_range(&self) -> (usize, usize) {
         let start = self.top_line;
         let count = self.terminal_height as usize;
         (start, count)
     }
 
-    pub fn get_percentage(&self) -> u8 {
-        if self.total_lines == 0 {
-            return 100;
+    /// The last line at least partly on screen.
+    pub fn bottom_line(&self, rows_of: &mut RowsOf) -> usize {
+        let mut rows_left = self.terminal_height as usize + self.top_row;
+        let mut line = self.top_line;
+        while line + 1 < self.total_lines {
+            let rows = self.rows(line, rows_of);
+            if rows >= rows_left {
+                break;
+            }
+            rows_left -= rows;
+            line += 1;
         }
-        if self.is_at_end() {
-            return 100;
-        }
-        ((self.top_line as f64 / self.total_lines as f64) * 100.0) as u8
+        line
     }
 
-    pub fn is_at_end(&self) -> bool {
-        self.top_line >= self.max_top_line() && self.max_top_line() > 0
+    pub fn total_lines(&self) -> usize {
+        self.total_lines
     }
 
     pub fn top_line(&self) -> usize {
         self.top_line
     }
 
-    pub fn terminal_width(&self) -> u16 {
-        self.terminal_width
+    pub fn top_row(&self) -> usize {
+        self.top_row
+    }
+
+    pub fn left_column(&self) -> usize {
+        self.left_column
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A view of `rows.len()` lines, where line `i` wraps onto `rows[i]`
+    /// rows, with three rows for text above the status line.
+    fn view_of(rows: &[usize]) -> ViewState {
+        let mut view = ViewState::new(20, 4, rows.len());
+        view.refit(&mut |line, _| rows[line]);
+        view
+    }
+
+    #[test]
+    fn scrolling_steps_through_the_rows_of_a_wrapped_line() {
+        let rows = [1, 3, 1, 1, 1, 1];
+        let rows_of = &mut |line: usize, _: usize| rows[line];
+        let mut view = view_of(&rows);
+
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            view.scroll_down_line(rows_of);
+            seen.push((view.top_line(), view.top_row()));
+        }
+        assert_eq!(seen, [(1, 0), (1, 1), (1, 2), (2, 0)]);
+
+        view.scroll_up_line(rows_of);
+        assert_eq!((view.top_line(), view.top_row()), (1, 2));
+        view.scroll_up_page(rows_of);
+        assert_eq!((view.top_line(), view.top_row()), (0, 0));
+    }
+
+    #[test]
+    fn the_end_can_start_part_way_through_a_line() {
+        let rows = [1, 1, 5];
+        let rows_of = &mut |line: usize, _: usize| rows[line];
+        let mut view = view_of(&rows);
+
+        view.jump_to_end();
+        assert_eq!((view.top_line(), view.top_row()), (2, 2));
+        view.scroll_down_page(rows_of);
+        assert_eq!((view.top_line(), view.top_row()), (2, 2));
+
+        view.jump_to_start();
+        view.scroll_down_page(rows_of);
+        view.scroll_down_page(rows_of);
+        assert_eq!((view.top_line(), view.top_row()), (2, 2));
+    }
+
+    #[test]
+    fn without_wrapping_every_line_is_one_row() {
+        let rows = [1, 1, 5];
+        let rows_of = &mut |line: usize, _: usize| rows[line];
+        let mut view = view_of(&rows);
+        view.set_wrap(false);
+        view.refit(rows_of);
+        view.jump_to_end();
+        assert_eq!((view.top_line(), view.top_row()), (0, 0));
+    }
+
+    #[test]
+    fn widening_the_terminal_keeps_the_top_row_within_its_line() {
+        // The first line is 40 columns wide
+        let rows_of = &mut |line: usize, width: usize| if line == 0 { 40 / width } else { 1 };
+        let mut view = ViewState::new(10, 4, 10);
+        view.refit(rows_of);
+        for _ in 0..3 {
+            view.scroll_down_line(rows_of);
+        }
+        assert_eq!((view.top_line(), view.top_row()), (0, 3));
+
+        view.update_dimensions(20, 4, rows_of);
+        assert_eq!((view.top_line(), view.top_row()), (0, 1));
+        view.update_dimensions(40, 4, rows_of);
+        assert_eq!((view.top_line(), view.top_row()), (0, 0));
+    }
+
+    #[test]
+    fn shrinking_the_file_pulls_the_top_back_to_the_end() {
+        let rows = [1, 1, 2, 4, 1, 1, 1, 1];
+        let rows_of = &mut |line: usize, _: usize| rows[line];
+        let mut view = view_of(&rows);
+        view.jump_to_line(6);
+        assert_eq!((view.top_line(), view.top_row()), (5, 0));
+
+        view.update_total_lines(4, 4, rows_of);
+        assert_eq!((view.top_line(), view.top_row()), (3, 1));
     }
 
-    fn max_top_line(&self) -> usize {
-        self.total_lines.saturating_sub(self.terminal_height as usize)
+    #[test]
+    fn percentages_count_lines_and_stop_at_the_end() {
+        let rows = [3; 10];
+        let mut view = view_of(&rows);
+        view.jump_to_percentage(50);
+        assert_eq!((view.top_line(), view.top_row()), (5, 0));
+        view.jump_to_percentage(100);
+        assert_eq!((view.top_line(), view.top_row()), (9, 0));
+        view.jump_to_percentage(200);
+        assert_eq!((view.top_line(), view.top_row()), (9, 0));
     }
 }
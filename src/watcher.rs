@@ -1,8 +1,8 @@
 use crate::error::Result;
-use notify::{RecommendedWatcher, RecursiveMode, Watcher};
-use notify_debouncer_full::{new_debouncer, DebouncedEvent, Debouncer, FileIdMap};
+use notify::{PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use notify_debouncer_full::{new_debouncer_opt, DebouncedEvent, Debouncer, FileIdMap};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::{channel, Receiver};
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::time::Duration;
 
 #[derive(Debug, PartialEq)]
@@ -13,33 +13,76 @@ pub enum FileEvent {
     Created,
 }
 
+type DebounceResult = std::result::Result<Vec<DebouncedEvent>, Vec<notify::Error>>;
+
+/// How file changes are noticed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatcherBackend {
+    /// The platform's change notifications (inotify, FSEvents, ...)
+    Native,
+    /// Periodically scanning the directory, for network and virtual
+    /// filesystems that don't deliver native events
+    Poll,
+}
+
+/// Keeps the debouncer for whichever backend is in use alive. It is never
+/// read; dropping it stops watching.
+#[allow(dead_code)]
+enum ActiveDebouncer {
+    Native(Debouncer<RecommendedWatcher, FileIdMap>),
+    Poll(Debouncer<PollWatcher, FileIdMap>),
+}
+
 pub struct FileWatcher {
-    _debouncer: Debouncer<RecommendedWatcher, FileIdMap>,
-    receiver: Receiver<std::result::Result<Vec<DebouncedEvent>, Vec<notify::Error>>>,
+    _debouncer: ActiveDebouncer,
+    receiver: Receiver<DebounceResult>,
     watched_path: PathBuf,
 }
 
 impl FileWatcher {
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        debounce: Duration,
+        backend: WatcherBackend,
+    ) -> Result<Self> {
         let path = path.as_ref().to_path_buf();
-        let watched_path = path.canonicalize().unwrap_or_else(|_| path.clone());
+        let watched_path = path.canonicalize().unwrap_or_else(|_| {
+            // A file that doesn't exist yet is matched by its name in its
+            // parent directory, which events name by its canonical path
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            match (parent.canonicalize(), path.file_name()) {
+                (Ok(parent), Some(name)) => parent.join(name),
+                _ => path.clone(),
+            }
+        });
 
         let (tx, rx) = channel();
 
-        // Create debouncer with 100ms timeout
-        let mut debouncer = new_debouncer(Duration::from_millis(100), None, tx)?;
-
         // Watch the parent directory for better compatibility across platforms
         // Watching a file directly doesn't always work, especially on macOS
-        let watch_path = if watched_path.is_file() {
-            watched_path.parent().unwrap_or(&watched_path)
-        } else {
+        let watch_path = if watched_path.is_dir() {
             &watched_path
+        } else {
+            watched_path.parent().unwrap_or(&watched_path)
         };
 
-        debouncer
-            .watcher()
-            .watch(watch_path, RecursiveMode::NonRecursive)?;
+        let debouncer = match backend {
+            WatcherBackend::Native => ActiveDebouncer::Native(Self::start_debouncer(
+                watch_path,
+                debounce,
+                notify::Config::default(),
+                tx,
+            )?),
+            WatcherBackend::Poll => ActiveDebouncer::Poll(Self::start_debouncer(
+                watch_path,
+                debounce,
+                notify::Config::default().with_poll_interval(debounce),
+                tx,
+            )?),
+        };
 
         Ok(Self {
             _debouncer: debouncer,
@@ -48,6 +91,22 @@ impl FileWatcher {
         })
     }
 
+    fn start_debouncer<W: Watcher>(
+        watch_path: &Path,
+        debounce: Duration,
+        config: notify::Config,
+        tx: Sender<DebounceResult>,
+    ) -> Result<Debouncer<W, FileIdMap>> {
+        let mut debouncer =
+            new_debouncer_opt::<_, W, _>(debounce, None, tx, FileIdMap::new(), config)?;
+
+        debouncer
+            .watcher()
+            .watch(watch_path, RecursiveMode::NonRecursive)?;
+
+        Ok(debouncer)
+    }
+
     pub fn check_for_changes(&mut self) -> Result<FileEvent> {
         // Non-blocking check for events
         match self.receiver.try_recv() {